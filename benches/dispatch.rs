@@ -0,0 +1,103 @@
+//! dispatch throughput of `go!` and of the runtime handle lookup behind it, run with
+//! `cargo bench --bench dispatch`
+use std::time::{Duration, Instant};
+use tokio_go::go;
+use tokio_go::prelude::*;
+
+const CALLS: u32 = 100_000;
+const CALLERS: u32 = 8;
+const THREADS: u32 = 32;
+
+async fn sequential() -> Duration {
+    let start = Instant::now();
+    for i in 0..CALLS {
+        let r = go!(
+            |tx: Sender<u32>| async move {
+                let _ = tx.send(i);
+            },
+            Context {
+                profile: 1,
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r, Ok(i));
+    }
+    start.elapsed()
+}
+
+async fn concurrent() -> Duration {
+    let start = Instant::now();
+    let callers: Vec<_> = (0..CALLERS)
+        .map(|_| {
+            tokio::spawn(async {
+                for i in 0..CALLS / CALLERS {
+                    let r = go!(
+                        |tx: Sender<u32>| async move {
+                            let _ = tx.send(i);
+                        },
+                        Context {
+                            profile: 1,
+                            ..Default::default()
+                        }
+                    )
+                    .await;
+                    assert_eq!(r, Ok(i));
+                }
+            })
+        })
+        .collect();
+    for c in callers {
+        c.await.unwrap();
+    }
+    start.elapsed()
+}
+
+/// `CALLS` handle lookups split over `THREADS` OS threads, through `lookup`
+fn contended(lookup: fn() -> Handle) -> Duration {
+    let start = Instant::now();
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            std::thread::spawn(move || {
+                for _ in 0..CALLS / THREADS {
+                    std::hint::black_box(lookup());
+                }
+            })
+        })
+        .collect();
+    for t in threads {
+        t.join().unwrap();
+    }
+    start.elapsed()
+}
+
+/// the lookup `go!` did before the handle snapshot: read the lock, clone the handle
+fn locked_lookup() -> Handle {
+    RUNTIMES.read().unwrap()[1]
+        .as_ref()
+        .unwrap()
+        .handle()
+        .clone()
+}
+
+fn snapshot_lookup() -> Handle {
+    runtime_handle(1)
+}
+
+fn report(name: &str, elapsed: Duration) {
+    let per_sec = CALLS as f64 / elapsed.as_secs_f64();
+    println!(
+        "{:<12} {:>10.0} calls/s ({:?} for {} calls)",
+        name, per_sec, elapsed, CALLS
+    );
+}
+
+#[tokio::main]
+async fn main() {
+    init_runtime(1);
+    sequential().await;
+    report("sequential", sequential().await);
+    report("concurrent", concurrent().await);
+    report("locked", contended(locked_lookup));
+    report("snapshot", contended(snapshot_lookup));
+}
@@ -0,0 +1,68 @@
+use crate::prelude::*;
+use core_affinity::CoreId;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// initialize `profile` with one worker per entry of `core_ids`, each thread pinned to its
+/// core (round robin, so blocking pool threads get pinned as well). a no-op if the profile is
+/// already initialized, like `init_runtime`. panics if `core_ids` is empty, or like
+/// `init_runtime` on the slot of a named profile.
+///
+/// pinning is best effort: it is supported on linux, android, windows and freebsd; elsewhere
+/// (notably macOS) the threads simply run unpinned.
+pub fn init_runtime_pinned(profile: u8, core_ids: &[usize]) {
+    assert!(
+        !core_ids.is_empty(),
+        "init_runtime_pinned needs at least one core"
+    );
+    crate::profile::assert_numbered(profile);
+    let cores: Vec<CoreId> = core_ids.iter().map(|id| CoreId { id: *id }).collect();
+    let cfg = RuntimeConfig {
+        worker_threads: Some(cores.len()),
+        ..Default::default()
+    };
+    RUNTIMES.install_with(profile, move || {
+        let next = AtomicUsize::new(0);
+        cfg.builder()
+            .on_thread_start(move || {
+                let core = cores[next.fetch_add(1, Ordering::Relaxed) % cores.len()];
+                core_affinity::set_for_current(core);
+            })
+            .build()
+            .unwrap()
+    });
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use crate::go;
+    use crate::prelude::*;
+
+    fn current_cpus() -> Vec<usize> {
+        unsafe {
+            let mut set: libc::cpu_set_t = std::mem::zeroed();
+            assert_eq!(
+                libc::sched_getaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &mut set),
+                0
+            );
+            (0..libc::CPU_SETSIZE as usize)
+                .filter(|cpu| libc::CPU_ISSET(*cpu, &set))
+                .collect()
+        }
+    }
+
+    #[tokio::test]
+    async fn pinned_worker_reports_affinity() {
+        init_runtime_pinned(32, &[0]);
+        let cpus = go!(
+            |tx: Sender<Vec<usize>>| async move {
+                let _ = tx.send(current_cpus());
+            },
+            Context {
+                profile: 32,
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(cpus, Ok(vec![0]));
+    }
+}
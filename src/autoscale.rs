@@ -0,0 +1,229 @@
+use crate::prelude::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::PoisonError;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// tokio's default `max_blocking_threads`, which `init_runtime` runtimes are built with
+pub const MAX_BLOCKING_THREADS: usize = 512;
+
+/// how often the autoscale monitor samples a profile's blocking backlog
+const AUTOSCALE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// the blocking concurrency limit of one profile and the numbers the monitor scales it by
+#[derive(Debug)]
+struct BlockingScaler {
+    permits: Arc<Semaphore>,
+    limit: AtomicUsize,
+    queued: AtomicUsize,
+    min: usize,
+    max: usize,
+}
+
+impl BlockingScaler {
+    fn busy(&self) -> usize {
+        self.limit
+            .load(Ordering::Relaxed)
+            .saturating_sub(self.permits.available_permits())
+    }
+
+    /// move the limit towards `blocking_target`. shrinking only takes idle permits, so
+    /// running closures are never cut short and a shrink may finish on a later tick.
+    fn rescale(&self) {
+        let limit = self.limit.load(Ordering::Relaxed);
+        let target = blocking_target(
+            limit,
+            self.busy(),
+            self.queued.load(Ordering::Relaxed),
+            self.min,
+            self.max,
+        );
+        if target > limit {
+            self.permits.add_permits(target - limit);
+            self.limit.store(target, Ordering::Relaxed);
+        } else if target < limit {
+            let idle = self.permits.available_permits().min(limit - target);
+            if idle == 0 {
+                return;
+            }
+            if let Ok(p) = self.permits.try_acquire_many(idle as u32) {
+                p.forget();
+                self.limit.store(limit - idle, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+static SCALERS: [RwLock<Option<Arc<BlockingScaler>>>; 256] = [const { RwLock::new(None) }; 256];
+
+fn scaler(profile: u8) -> Option<Arc<BlockingScaler>> {
+    SCALERS[profile as usize]
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// the limit the monitor aims for: grow by the backlog when closures are waiting, shrink
+/// to half (but not below what is busy) when at most half of the limit is in use
+pub(crate) fn blocking_target(
+    limit: usize,
+    busy: usize,
+    queued: usize,
+    min: usize,
+    max: usize,
+) -> usize {
+    let target = if queued > 0 {
+        limit + queued
+    } else if busy * 2 <= limit {
+        (limit / 2).max(busy)
+    } else {
+        limit
+    };
+    target.clamp(min, max)
+}
+
+/// adapt how many `run_blocking` closures may run at once on `profile` to the observed
+/// backlog, between `min` and `max` (capped at `MAX_BLOCKING_THREADS`, and at least 1).
+///
+/// tokio fixes `max_blocking_threads` when a runtime is built, so the pool itself can't be
+/// resized; the monitor scales a limit in front of it instead, which bounds how many
+/// blocking threads the profile keeps busy. it runs on the profile's runtime until
+/// `disable_blocking_autoscale` or another `enable_blocking_autoscale` call replaces it.
+pub fn enable_blocking_autoscale(profile: u8, min: usize, max: usize) {
+    let max = max.clamp(1, MAX_BLOCKING_THREADS);
+    let min = min.clamp(1, max);
+    let own = Arc::new(BlockingScaler {
+        permits: Arc::new(Semaphore::new(min)),
+        limit: AtomicUsize::new(min),
+        queued: AtomicUsize::new(0),
+        min,
+        max,
+    });
+    *SCALERS[profile as usize]
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(own.clone());
+    runtime_handle(profile).spawn(async move {
+        let mut tick = interval(AUTOSCALE_INTERVAL);
+        loop {
+            tick.tick().await;
+            match scaler(profile) {
+                Some(current) if Arc::ptr_eq(&current, &own) => own.rescale(),
+                _ => return,
+            }
+        }
+    });
+}
+
+/// stop scaling `profile`; `run_blocking` goes back to tokio's pool limit alone
+pub fn disable_blocking_autoscale(profile: u8) {
+    *SCALERS[profile as usize]
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// the current autoscaled limit of `profile`, `None` when autoscale is off
+pub fn blocking_limit(profile: u8) -> Option<usize> {
+    scaler(profile).map(|s| s.limit.load(Ordering::Relaxed))
+}
+
+/// a slot to run one blocking closure on `profile` in when `enable_blocking_autoscale` is
+/// on, waiting for one to free up. `None` when autoscale is off
+pub(crate) async fn blocking_permit(profile: u8) -> Option<OwnedSemaphorePermit> {
+    let s = scaler(profile)?;
+    s.queued.fetch_add(1, Ordering::Relaxed);
+    let _queued = Queued(&s.queued);
+    s.permits.clone().acquire_owned().await.ok()
+}
+
+/// takes a waiter off a scaler's backlog when its wait ends, also by being dropped
+struct Queued<'a>(&'a AtomicUsize);
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// run the blocking closure `f` on the blocking pool of `profile` and wait for its value,
+/// waiting first for a slot when `enable_blocking_autoscale` is on. `go_blocking` with
+/// `Context::profile(profile)`, so a panic comes back as `GoError::Panicked`.
+pub async fn run_blocking<R, F>(profile: u8, f: F) -> Result<R, GoError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    go_blocking(f, Context::profile(profile)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{blocking_permit, blocking_target, scaler};
+    use crate::prelude::*;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn target_follows_backlog() {
+        assert_eq!(blocking_target(2, 2, 3, 1, 8), 5);
+        assert_eq!(blocking_target(6, 6, 10, 1, 8), 8);
+        assert_eq!(blocking_target(8, 3, 0, 1, 8), 4);
+        assert_eq!(blocking_target(8, 5, 0, 1, 8), 8);
+        assert_eq!(blocking_target(2, 0, 0, 2, 8), 2);
+    }
+
+    /// wait up to a second for the scaler to move `profile`'s limit to `limit`
+    async fn settles_at(profile: u8, limit: usize) -> bool {
+        for _ in 0..100 {
+            if blocking_limit(profile) == Some(limit) {
+                return true;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        false
+    }
+
+    #[tokio::test]
+    async fn limit_grows_under_backlog_and_shrinks_after() {
+        enable_blocking_autoscale(39, 1, 4);
+        assert_eq!(blocking_limit(39), Some(1));
+        let jobs: Vec<_> = (0..8)
+            .map(|i| {
+                tokio::spawn(run_blocking(39, move || {
+                    std::thread::sleep(Duration::from_millis(150));
+                    i
+                }))
+            })
+            .collect();
+        assert!(settles_at(39, 4).await);
+        for (i, job) in jobs.into_iter().enumerate() {
+            assert_eq!(job.await.unwrap(), Ok(i));
+        }
+        assert!(settles_at(39, 1).await);
+        disable_blocking_autoscale(39);
+        assert_eq!(blocking_limit(39), None);
+        assert_eq!(run_blocking(39, || 1).await, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn abandoned_waiters_leave_the_backlog() {
+        enable_blocking_autoscale(81, 1, 1);
+        let held = blocking_permit(81).await;
+        assert!(held.is_some());
+        let waiting = tokio::time::timeout(Duration::from_millis(20), blocking_permit(81)).await;
+        assert!(waiting.is_err());
+        assert_eq!(scaler(81).unwrap().queued.load(Ordering::Relaxed), 0);
+        disable_blocking_autoscale(81);
+    }
+
+    #[tokio::test]
+    async fn run_blocking_is_admitted_like_go() {
+        set_draining(84, true);
+        assert_eq!(run_blocking(84, || 1).await, Err(GoError::Draining));
+        set_draining(84, false);
+        assert_eq!(run_blocking(84, || 1).await, Ok(1));
+        let r = run_blocking(84, || panic!("blocking")).await;
+        assert_eq!(r, Err::<(), _>(GoError::Panicked("blocking".to_string())));
+        assert_eq!(
+            last_error(84),
+            Some(GoError::Panicked("blocking".to_string()))
+        );
+    }
+}
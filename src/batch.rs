@@ -0,0 +1,543 @@
+use crate::dispatch::{spawn_task, within, TaskEnd};
+use crate::prelude::{runtime_handle, Context, GoError, Sender};
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{Duration, Instant};
+use tokio::task::AbortHandle;
+
+/// how many items `go_reduce` keeps running at once
+const REDUCE_WINDOW: usize = 64;
+
+/// spawn `map_fn` for every item on the runtime of `profile` and fold the results with
+/// `reduce_fn` in completion order (not input order).
+///
+/// at most 64 items run at once, the next one is spawned as soon as a result is folded, and
+/// results are folded on the awaiting task as they arrive, so memory stays flat however
+/// many items there are. the first item that fails (a panic inside `map_fn`, the runtime
+/// shutting down, the profile draining...) fails the whole call: the items still running
+/// are aborted and reported as `GoError::Cancelled`, the rest are never spawned.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// #[tokio::main]
+/// async fn main(){
+/// let sum = go_reduce(1, 1..=10u64, |x| async move { x * 2 }, |acc, x| acc + x, 0).await;
+/// assert_eq!(sum, Ok(110));
+/// }
+/// ```
+pub async fn go_reduce<I, T, R, M, Fut, F>(
+    profile: u8,
+    items: I,
+    map_fn: M,
+    mut reduce_fn: F,
+    init: R,
+) -> Result<R, GoError>
+where
+    I: IntoIterator,
+    M: Fn(I::Item) -> Fut,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+    F: FnMut(R, T) -> R,
+{
+    let ctx = Context::profile(profile);
+    let handle = runtime_handle(profile);
+    let mut items = items.into_iter().enumerate();
+    // the running items by index, to cancel them when one fails
+    let mut running: HashMap<usize, (AbortHandle, TaskEnd)> = HashMap::new();
+    let mut results = FuturesUnordered::new();
+    let mut acc = init;
+    let failed = 'reduce: loop {
+        while results.len() < REDUCE_WINDOW {
+            let (i, item) = match items.next() {
+                Some(next) => next,
+                None => break,
+            };
+            let fut = map_fn(item);
+            let send = |tx: Sender<T>| async move {
+                let _ = tx.send(fut.await);
+            };
+            let task = match spawn_task(&handle, send, &ctx).await {
+                Ok(task) => task,
+                Err(e) => break 'reduce e,
+            };
+            running.insert(i, (task.abort_handle(), task.end()));
+            let ctx = &ctx;
+            results.push(async move { (i, task.wait(ctx, false).await.0) });
+        }
+        match results.next().await {
+            None => return Ok(acc),
+            Some((i, r)) => {
+                running.remove(&i);
+                match r {
+                    Ok(v) => acc = reduce_fn(acc, v),
+                    Err(e) => break e,
+                }
+            }
+        }
+    };
+    drop(results);
+    let cancelled = Err::<T, _>(GoError::Cancelled);
+    for (abort, end) in running.into_values() {
+        abort.abort();
+        end.report(&cancelled);
+    }
+    Err(failed)
+}
+
+/// per item outcome of a batch, in input order
+#[derive(Debug)]
+pub struct BatchResult<T> {
+    pub results: Vec<Result<T, GoError>>,
+}
+
+impl<T> BatchResult<T> {
+    /// values of the items that completed
+    pub fn successes(&self) -> impl Iterator<Item = &T> {
+        self.results.iter().filter_map(|r| r.as_ref().ok())
+    }
+
+    /// number of items cut off by the batch or per task deadline
+    pub fn timed_out(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| matches!(r, Err(GoError::Timeout { .. })))
+            .count()
+    }
+
+    /// whether every item completed
+    pub fn is_complete(&self) -> bool {
+        self.results.iter().all(|r| r.is_ok())
+    }
+}
+
+impl<T> IntoIterator for BatchResult<T> {
+    type Item = Result<T, GoError>;
+    type IntoIter = std::vec::IntoIter<Result<T, GoError>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.results.into_iter()
+    }
+}
+
+/// spawn `map_fn` for every item on `ctx.profile` and collect the values in input order.
+///
+/// `ctx.timeout` is a deadline for the whole batch: when it hits, the items that finished
+/// keep their value, the rest are aborted and reported as `GoError::Timeout`. with
+/// `ctx.per_task_timeout` it is a deadline for each item on its own instead, so a slow item
+/// only times out itself. the items run like the tasks of `go_all`, so a failing item (a
+/// panic inside `map_fn`, the runtime shutting down...) only fails its own slot.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// #[tokio::main]
+/// async fn main(){
+/// let batch = go_map(1..=3, |x| async move { x * 2 }, Context::default()).await;
+/// assert_eq!(batch.successes().copied().collect::<Vec<_>>(), vec![2, 4, 6]);
+/// }
+/// ```
+pub async fn go_map<I, T, M, Fut>(items: I, map_fn: M, ctx: Context) -> BatchResult<T>
+where
+    I: IntoIterator,
+    M: Fn(I::Item) -> Fut,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    let tasks = items.into_iter().map(|item| {
+        let fut = map_fn(item);
+        |tx: Sender<T>| async move {
+            let _ = tx.send(fut.await);
+        }
+    });
+    BatchResult {
+        results: go_all(tasks, ctx).await,
+    }
+}
+
+/// a `go!` style closure with its future boxed, so closures of different types fit in one batch
+#[doc(hidden)]
+pub type BoxedTask<T> =
+    Box<dyn FnOnce(Sender<T>) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+
+/// spawn every `go!` style closure in `tasks` on `ctx.profile` and wait for all of them,
+/// returning their results in input order. see `go_all!` for closures of different types.
+///
+/// like with `go_map`, `ctx.timeout` is a deadline shared by the whole batch: when it hits,
+/// the unfinished tasks are aborted and their slots come back as `GoError::Timeout`. with
+/// `ctx.per_task_timeout` it applies to each task on its own instead, as with `go!`.
+/// `ctx.batch_timeout` bounds the whole call in either case, the earlier deadline wins.
+/// a failing task (timeout, panic, dropped sender) only fails its own slot.
+pub async fn go_all<T, F, Fut, I>(tasks: I, ctx: Context) -> Vec<Result<T, GoError>>
+where
+    I: IntoIterator<Item = F>,
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let handle = runtime_handle(ctx.profile);
+    let mut spawned = Vec::new();
+    for f in tasks {
+        spawned.push(spawn_task(&handle, f, &ctx).await);
+    }
+    let shared = (!ctx.per_task_timeout && !ctx.timeout.is_zero()).then_some(ctx.timeout);
+    let deadline = shared.into_iter().chain(ctx.batch_timeout).min();
+    let task_ctx = match shared {
+        Some(_) => Context {
+            timeout: Duration::ZERO,
+            ..ctx.clone()
+        },
+        None => ctx.clone(),
+    };
+    let mut slots: Vec<Option<Result<T, GoError>>> = spawned.iter().map(|_| None).collect();
+    let mut aborts = Vec::new();
+    let mut pending = FuturesUnordered::new();
+    for (i, task) in spawned.into_iter().enumerate() {
+        match task {
+            Ok(task) => {
+                aborts.push((i, task.abort_handle(), task.end()));
+                let task_ctx = &task_ctx;
+                pending.push(async move { (i, task.wait(task_ctx, false).await.0) });
+            }
+            Err(e) => slots[i] = Some(Err(e)),
+        }
+    }
+    let collect = async {
+        while let Some((i, r)) = pending.next().await {
+            slots[i] = Some(r);
+        }
+    };
+    match deadline {
+        Some(limit) => {
+            within(&ctx, limit, collect).await;
+        }
+        None => collect.await,
+    }
+    for (i, abort, end) in aborts {
+        if slots[i].is_none() {
+            abort.abort();
+            let r = Err(GoError::Timeout { attempts: 1 });
+            end.report(&r);
+            slots[i] = Some(r);
+        }
+    }
+    slots.into_iter().map(|slot| slot.unwrap()).collect()
+}
+
+/// drive all `tasks` concurrently and yield `(index, result, elapsed)` for each one in the
+/// order they complete. `elapsed` is measured from the call to `race_all`.
+/// see `go_race_all!` for racing futures of different types.
+pub fn race_all<I, Fut, T>(tasks: I) -> impl Stream<Item = (usize, Result<T, GoError>, Duration)>
+where
+    I: IntoIterator<Item = Fut>,
+    Fut: Future<Output = Result<T, GoError>>,
+{
+    let start = Instant::now();
+    tasks
+        .into_iter()
+        .enumerate()
+        .map(|(i, task)| async move {
+            let r = task.await;
+            (i, r, start.elapsed())
+        })
+        .collect::<FuturesUnordered<_>>()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go;
+    use crate::go_all;
+    use crate::go_race_all;
+    use crate::prelude::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn reduce_sum_of_squares() {
+        let sum = go_reduce(
+            10,
+            1..=1000u64,
+            |x| async move { x * x },
+            |acc, x| acc + x,
+            0,
+        )
+        .await;
+        assert_eq!(sum, Ok((1..=1000u64).map(|x| x * x).sum::<u64>()));
+    }
+
+    #[tokio::test]
+    async fn batches_report_failures_instead_of_panicking() {
+        let batch = go_map(
+            0..3u64,
+            |i| async move {
+                if i == 1 {
+                    panic!("item 1");
+                }
+                i
+            },
+            Context::profile(73),
+        )
+        .await;
+        assert_eq!(
+            batch.results,
+            vec![Ok(0), Err(GoError::Panicked("item 1".to_string())), Ok(2)]
+        );
+        assert_eq!(
+            go_reduce(
+                73,
+                0..3u64,
+                |i| async move {
+                    if i == 1 {
+                        panic!("item 1");
+                    }
+                    i
+                },
+                |a, x| a + x,
+                0
+            )
+            .await,
+            Err(GoError::Panicked("item 1".to_string()))
+        );
+
+        let map = tokio::spawn(go_map(
+            0..2u64,
+            |i| async move {
+                sleep(Duration::from_secs(5)).await;
+                i
+            },
+            Context::profile(73),
+        ));
+        let reduce = tokio::spawn(go_reduce(
+            73,
+            0..2u64,
+            |i| async move {
+                sleep(Duration::from_secs(5)).await;
+                i
+            },
+            |a, x| a + x,
+            0,
+        ));
+        while in_flight(73) < 4 {
+            sleep(Duration::from_millis(5)).await;
+        }
+        shutdown_runtime(73, Duration::ZERO);
+        assert_eq!(
+            map.await.unwrap().results,
+            vec![Err(GoError::RuntimeShutdown), Err(GoError::RuntimeShutdown)]
+        );
+        assert_eq!(reduce.await.unwrap(), Err(GoError::RuntimeShutdown));
+    }
+
+    #[tokio::test]
+    async fn reduce_runs_a_bounded_window_and_cancels_the_rest() {
+        use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+        let running = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let sum = go_reduce(
+            87,
+            0..300u64,
+            |i| {
+                let running = running.clone();
+                let peak = peak.clone();
+                async move {
+                    peak.fetch_max(running.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    sleep(Duration::from_millis(5)).await;
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    i
+                }
+            },
+            |a, x| a + x,
+            0,
+        )
+        .await;
+        assert_eq!(sum, Ok((0..300u64).sum::<u64>()));
+        assert!(peak.load(Ordering::SeqCst) <= 64);
+
+        #[derive(Default)]
+        struct Cancels(AtomicU32);
+        impl TaskObserver for Cancels {
+            fn on_cancel(&self, _profile: u8) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        let cancels = Arc::new(Cancels::default());
+        set_observer(87, cancels.clone());
+        let r = go_reduce(
+            87,
+            0..5u64,
+            |i| async move {
+                if i == 4 {
+                    panic!("item 4");
+                }
+                sleep(Duration::from_secs(5)).await;
+                i
+            },
+            |a, x| a + x,
+            0,
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Panicked("item 4".to_string())));
+        assert_eq!(cancels.0.load(Ordering::SeqCst), 4);
+        while in_flight(87) > 0 {
+            sleep(Duration::from_millis(5)).await;
+        }
+        clear_observer(87);
+    }
+
+    #[tokio::test]
+    async fn race_all_yields_in_completion_order() {
+        let slow = go!(
+            |tx: Sender<&str>| async move {
+                sleep(Duration::from_millis(300)).await;
+                let _ = tx.send("slow");
+            },
+            Context {
+                profile: 15,
+                ..Default::default()
+            }
+        );
+        let fast = go!(
+            |tx: Sender<&str>| async move {
+                sleep(Duration::from_millis(50)).await;
+                let _ = tx.send("fast");
+            },
+            Context {
+                profile: 15,
+                ..Default::default()
+            }
+        );
+        let finished: Vec<_> = go_race_all!(slow, fast).collect().await;
+        assert_eq!(finished.len(), 2);
+        let (i0, r0, d0) = &finished[0];
+        let (i1, r1, d1) = &finished[1];
+        assert_eq!((*i0, r0.clone()), (1, Ok("fast")));
+        assert_eq!((*i1, r1.clone()), (0, Ok("slow")));
+        assert!(d0 < d1);
+    }
+
+    #[tokio::test]
+    async fn all_batch_timeout_cancels_slow_tasks() {
+        let slow_finished = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let flag = slow_finished.clone();
+        let start = std::time::Instant::now();
+        let results = go_all!(
+            [
+                |tx: Sender<i32>| async move {
+                    let _ = tx.send(1);
+                },
+                |tx: Sender<i32>| async move {
+                    sleep(Duration::from_secs(5)).await;
+                    flag.store(true, std::sync::atomic::Ordering::SeqCst);
+                    let _ = tx.send(2);
+                },
+                |tx: Sender<i32>| async move {
+                    sleep(Duration::from_millis(20)).await;
+                    let _ = tx.send(3);
+                },
+                |_tx: Sender<i32>| async move {},
+            ],
+            Context {
+                profile: 35,
+                batch_timeout: Some(Duration::from_millis(300)),
+                ..Default::default()
+            }
+        )
+        .await;
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(
+            results,
+            vec![
+                Ok(1),
+                Err(GoError::Timeout { attempts: 1 }),
+                Ok(3),
+                Err(GoError::SenderDropped)
+            ]
+        );
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(in_flight(35), 0);
+        assert!(!slow_finished.load(std::sync::atomic::Ordering::SeqCst));
+    }
+
+    #[tokio::test]
+    async fn all_shares_one_deadline() {
+        let start = std::time::Instant::now();
+        let results = go_all!(
+            [
+                |tx: Sender<i32>| async move {
+                    let _ = tx.send(1);
+                },
+                |tx: Sender<i32>| async move {
+                    sleep(Duration::from_millis(60)).await;
+                    let _ = tx.send(2);
+                },
+                |tx: Sender<i32>| async move {
+                    sleep(Duration::from_millis(70)).await;
+                    let _ = tx.send(3);
+                },
+                |tx: Sender<i32>| async move {
+                    sleep(Duration::from_secs(5)).await;
+                    let _ = tx.send(4);
+                },
+            ],
+            Context::profile(63).timeout(Duration::from_millis(100))
+        )
+        .await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(100), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(500), "{:?}", elapsed);
+        assert_eq!(
+            results,
+            vec![Ok(1), Ok(2), Ok(3), Err(GoError::Timeout { attempts: 1 })]
+        );
+    }
+
+    #[tokio::test]
+    async fn map_per_task_timeout_fails_only_slow_items() {
+        let start = std::time::Instant::now();
+        let batch = go_map(
+            0..5u64,
+            |i| async move {
+                sleep(Duration::from_millis(if i == 2 { 5000 } else { 50 * i })).await;
+                i
+            },
+            Context {
+                profile: 49,
+                timeout: Duration::from_millis(300),
+                per_task_timeout: true,
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(
+            batch.successes().copied().collect::<Vec<_>>(),
+            vec![0, 1, 3, 4]
+        );
+        assert_eq!(batch.results[2], Err(GoError::Timeout { attempts: 1 }));
+    }
+
+    #[tokio::test]
+    async fn map_keeps_partial_results_on_timeout() {
+        let batch = go_map(
+            0..5u64,
+            |i| async move {
+                if i % 2 == 1 {
+                    sleep(Duration::from_secs(5)).await;
+                }
+                i
+            },
+            Context {
+                profile: 20,
+                timeout: Duration::from_millis(200),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(
+            batch.successes().copied().collect::<Vec<_>>(),
+            vec![0, 2, 4]
+        );
+        assert_eq!(batch.timed_out(), 2);
+        assert!(!batch.is_complete());
+        assert_eq!(batch.results[1], Err(GoError::Timeout { attempts: 1 }));
+    }
+}
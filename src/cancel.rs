@@ -0,0 +1,167 @@
+use pin_project_lite::pin_project;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context as TaskContext, Poll};
+use tokio::runtime::Handle;
+use tokio::task::futures::TaskLocalFuture;
+use tokio::task::AbortHandle;
+use tokio_util::sync::CancellationToken;
+
+type Cleanup = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+tokio::task_local! {
+    static ON_CANCEL: RefCell<Vec<Cleanup>>;
+}
+
+pin_project! {
+    /// runs the cleanups registered with `on_cancel!` if dropped before `inner` completes
+    pub(crate) struct CancelScope<F: Future> {
+        #[pin]
+        inner: TaskLocalFuture<RefCell<Vec<Cleanup>>, F>,
+        done: bool,
+    }
+
+    impl<F: Future> PinnedDrop for CancelScope<F> {
+        fn drop(this: Pin<&mut Self>) {
+            let this = this.project();
+            if *this.done {
+                return;
+            }
+            let cleanups = this.inner.take_value().map(RefCell::into_inner);
+            if let (Some(cleanups), Ok(handle)) = (cleanups, Handle::try_current()) {
+                for cleanup in cleanups {
+                    handle.spawn(cleanup);
+                }
+            }
+        }
+    }
+}
+
+impl<F: Future> Future for CancelScope<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<F::Output> {
+        let this = self.project();
+        let out = this.inner.poll(cx);
+        if out.is_ready() {
+            *this.done = true;
+        }
+        out
+    }
+}
+
+pub(crate) fn cancel_scope<F: Future>(fut: F) -> CancelScope<F> {
+    CancelScope {
+        inner: ON_CANCEL.scope(RefCell::new(Vec::new()), fut),
+        done: false,
+    }
+}
+
+/// register `cleanup` to be spawned if the current `go!` task is cancelled or aborted on
+/// timeout before it completes. nothing runs when the task completes normally.
+/// returns `false` (and drops `cleanup`) when not called from within a `go!` task.
+#[doc(hidden)]
+pub fn on_cancel<F>(cleanup: F) -> bool
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    ON_CANCEL
+        .try_with(|c| c.borrow_mut().push(Box::pin(cleanup)))
+        .is_ok()
+}
+
+/// cancels the task of a `go_cancelable!` call. clones cancel the same task, dropping them
+/// doesn't cancel anything.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    token: CancellationToken,
+    abort: Arc<OnceLock<AbortHandle>>,
+}
+
+impl CancelHandle {
+    /// abort the task and make the pending call return `GoError::Cancelled`, unless the
+    /// result was already sent. before the call is first awaited nothing has been spawned,
+    /// then the call fails right away without running the closure.
+    pub fn cancel(&self) {
+        self.token.cancel();
+        if let Some(abort) = self.abort.get() {
+            abort.abort();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// remember the spawned task, aborting it at once if `cancel` came first
+    pub(crate) fn attach(&self, abort: AbortHandle) {
+        let _ = self.abort.set(abort);
+        if self.token.is_cancelled() {
+            self.cancel();
+        }
+    }
+
+    pub(crate) fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::{go, on_cancel};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[tokio::test]
+    async fn cleanup_runs_only_on_cancel() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let completed = Arc::new(AtomicBool::new(false));
+        let flags = [cancelled.clone(), completed.clone()];
+        let ctx = Context {
+            profile: 21,
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let r = go!(
+            |tx: Sender<()>| {
+                let flags = flags.clone();
+                async move {
+                    let [cancelled, completed] = flags;
+                    assert!(on_cancel!(async move {
+                        cancelled.store(true, Ordering::SeqCst);
+                    }));
+                    sleep(Duration::from_secs(5)).await;
+                    let _ = tx.send(());
+                    completed.store(true, Ordering::SeqCst);
+                }
+            },
+            ctx.clone()
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+
+        let finished = Arc::new(AtomicBool::new(false));
+        let flag = finished.clone();
+        let r = go!(
+            |tx: Sender<()>| {
+                let flag = flag.clone();
+                async move {
+                    on_cancel!(async move {
+                        flag.store(true, Ordering::SeqCst);
+                    });
+                    let _ = tx.send(());
+                }
+            },
+            ctx
+        )
+        .await;
+        assert_eq!(r, Ok(()));
+        sleep(Duration::from_millis(100)).await;
+        assert!(cancelled.load(Ordering::SeqCst));
+        assert!(!completed.load(Ordering::SeqCst));
+        assert!(!finished.load(Ordering::SeqCst));
+        assert!(!on_cancel(async {}));
+    }
+}
@@ -0,0 +1,127 @@
+use crate::prelude::*;
+use futures::{Stream, StreamExt};
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use tokio::sync::mpsc::error::SendError;
+
+/// a go style bounded channel: values are sent into it from any number of tasks (through
+/// `sender()` clones) and received in order. it stays open while it or any sender is alive,
+/// `close()` drops its own sending end so it ends once the other senders are gone.
+#[derive(Debug)]
+pub struct Chan<T> {
+    sender: Option<mpsc::Sender<T>>,
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T: Send + 'static> Chan<T> {
+    /// a channel buffering up to `capacity` values, panics if `capacity` is 0
+    pub fn new(capacity: usize) -> Self {
+        let (sender, receiver) = mpsc::channel(capacity);
+        Chan {
+            sender: Some(sender),
+            receiver,
+        }
+    }
+
+    /// a closed channel yielding the items of `stream`, which is forwarded with
+    /// `DEFAULT_STREAM_CAPACITY` items of buffering by a task on the current runtime. that task
+    /// belongs to the caller rather than to any profile, so it isn't counted or limited by one.
+    /// panics outside a tokio runtime
+    pub fn from_stream<S>(stream: S) -> Self
+    where
+        S: Stream<Item = T> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(DEFAULT_STREAM_CAPACITY);
+        tokio::spawn(async move {
+            let mut stream = std::pin::pin!(stream);
+            while let Some(v) = stream.next().await {
+                if sender.send(v).await.is_err() {
+                    return;
+                }
+            }
+        });
+        Chan {
+            sender: None,
+            receiver,
+        }
+    }
+
+    /// a sender for this channel, `None` once it was closed
+    pub fn sender(&self) -> Option<mpsc::Sender<T>> {
+        self.sender.clone()
+    }
+
+    /// send `value`, waiting for room in the buffer. fails once the channel was closed
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match &self.sender {
+            Some(sender) => sender.send(value).await,
+            None => Err(SendError(value)),
+        }
+    }
+
+    /// the next value, `None` once the channel is closed and every sender is gone
+    pub async fn recv(&mut self) -> Option<T> {
+        self.receiver.recv().await
+    }
+
+    /// stop sending through this channel; values already sent can still be received
+    pub fn close(&mut self) {
+        self.sender = None;
+    }
+
+    /// close the channel and read the rest of it as a `Stream`
+    pub fn into_stream(self) -> ChanStream<T> {
+        ChanStream {
+            receiver: self.receiver,
+        }
+    }
+}
+
+/// the receiving end of a `Chan` as a `Stream`, see `Chan::into_stream`
+#[derive(Debug)]
+pub struct ChanStream<T> {
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T> Stream for ChanStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<T>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use futures::StreamExt;
+
+    #[tokio::test]
+    async fn chan_round_trips_through_stream() {
+        let chan = Chan::new(8);
+        let sender = chan.sender().unwrap();
+        tokio::spawn(async move {
+            for i in 1..=3 {
+                let _ = sender.send(i).await;
+            }
+        });
+        let doubled: Vec<i32> = chan.into_stream().map(|x| x * 2).collect().await;
+        assert_eq!(doubled, vec![2, 4, 6]);
+
+        let mut chan = Chan::from_stream(futures::stream::iter(doubled));
+        assert!(chan.send(0).await.is_err());
+        let mut received = Vec::new();
+        while let Some(v) = chan.recv().await {
+            received.push(v);
+        }
+        assert_eq!(received, vec![2, 4, 6]);
+    }
+
+    #[tokio::test]
+    async fn from_stream_forwards_on_the_callers_runtime() {
+        let caller = std::thread::current().id();
+        let forwarded = futures::stream::once(async { std::thread::current().id() });
+        let mut chan = Chan::from_stream(forwarded);
+        assert_eq!(chan.recv().await, Some(caller));
+    }
+}
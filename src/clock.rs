@@ -0,0 +1,102 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::sync::watch;
+
+/// time source used for `Context.timeout` when set as `Context.clock`.
+/// without one, timeouts follow tokio's timer (wall time).
+pub trait Clock: Send + Sync + Debug {
+    /// resolve once `d` has passed on this clock, counting from the call
+    fn sleep(&self, d: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>>;
+}
+
+/// a manually driven clock for simulations: time only moves on `advance`/`set`
+#[derive(Debug)]
+pub struct VirtualClock {
+    now: watch::Sender<Duration>,
+}
+
+impl VirtualClock {
+    /// a clock starting at `Duration::ZERO`
+    pub fn new() -> Self {
+        VirtualClock {
+            now: watch::Sender::new(Duration::ZERO),
+        }
+    }
+
+    /// current simulated time
+    pub fn now(&self) -> Duration {
+        *self.now.borrow()
+    }
+
+    /// move simulated time forward by `d`, waking every sleep that is due
+    pub fn advance(&self, d: Duration) {
+        self.now.send_modify(|now| *now += d);
+    }
+
+    /// jump to the simulated instant `t`; going backwards is ignored
+    pub fn set(&self, t: Duration) {
+        self.now.send_if_modified(|now| {
+            let forward = t > *now;
+            if forward {
+                *now = t;
+            }
+            forward
+        });
+    }
+}
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock::new()
+    }
+}
+
+impl Clock for VirtualClock {
+    fn sleep(&self, d: Duration) -> Pin<Box<dyn Future<Output = ()> + Send>> {
+        let mut now = self.now.subscribe();
+        let deadline = *now.borrow() + d;
+        Box::pin(async move {
+            // an error means the clock is gone, so time will never reach the deadline
+            if now.wait_for(|t| *t >= deadline).await.is_err() {
+                futures::future::pending::<()>().await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go;
+    use crate::prelude::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn timeout_follows_virtual_clock() {
+        let clock = Arc::new(VirtualClock::new());
+        let start = Instant::now();
+        let call = go!(
+            |tx: Sender<()>| async move {
+                sleep(Duration::from_secs(60)).await;
+                let _ = tx.send(());
+            },
+            Context {
+                profile: 19,
+                timeout: Duration::from_secs(10),
+                clock: Some(clock.clone()),
+                ..Default::default()
+            }
+        );
+        let driver = async {
+            sleep(Duration::from_millis(20)).await;
+            clock.advance(Duration::from_secs(9));
+            sleep(Duration::from_millis(20)).await;
+            clock.advance(Duration::from_secs(1));
+        };
+        let (r, _) = tokio::join!(call, driver);
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        assert_eq!(clock.now(), Duration::from_secs(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -0,0 +1,167 @@
+use crate::prelude::*;
+use std::sync::PoisonError;
+
+/// every profile an application sets up front, see `configure_from`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GoConfig {
+    pub profiles: Vec<ProfileConfig>,
+}
+
+/// the settings of one profile. fields left `None` keep tokio's or the crate's defaults
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ProfileConfig {
+    pub profile: u8,
+    /// a label for logs and diagnostics
+    pub name: Option<String>,
+    /// `set_default_worker_threads` (or tokio's default) when `None`
+    pub worker_threads: Option<usize>,
+    pub max_blocking_threads: Option<usize>,
+    /// the `Context.timeout` handed out by `context()`, in milliseconds
+    pub timeout_ms: Option<u64>,
+    /// see `set_max_outstanding`
+    pub max_outstanding: Option<u64>,
+}
+
+impl ProfileConfig {
+    /// a `Context` for this profile with the configured timeout
+    pub fn context(&self) -> Context {
+        Context {
+            profile: self.profile,
+            timeout: Duration::from_millis(self.timeout_ms.unwrap_or(0)),
+            ..Default::default()
+        }
+    }
+}
+
+static CONFIGS: [RwLock<Option<ProfileConfig>>; 256] = [const { RwLock::new(None) }; 256];
+
+/// initialize every profile listed in `config` with its settings, in one go.
+///
+/// like `init_runtime`, a profile whose runtime already exists keeps it (its worker and
+/// blocking thread counts can't change anymore), the other settings are applied anyway.
+/// with the `serde` feature the config can be deserialized, e.g. from a JSON file.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// configure_from(GoConfig {
+///     profiles: vec![ProfileConfig {
+///         profile: 2,
+///         name: Some("io".to_string()),
+///         worker_threads: Some(1),
+///         ..Default::default()
+///     }],
+/// });
+/// assert_eq!(profile_config(2).unwrap().name.as_deref(), Some("io"));
+/// ```
+pub fn configure_from(config: GoConfig) {
+    for p in config.profiles {
+        init_runtime_with(
+            p.profile,
+            RuntimeConfig {
+                worker_threads: p.worker_threads,
+                max_blocking_threads: p.max_blocking_threads,
+                ..Default::default()
+            },
+        );
+        if let Some(max) = p.max_outstanding {
+            set_max_outstanding(p.profile, max);
+        }
+        let slot = &CONFIGS[p.profile as usize];
+        *slot.write().unwrap_or_else(PoisonError::into_inner) = Some(p);
+    }
+}
+
+/// the settings `profile` was given through `configure_from`, if any
+pub fn profile_config(profile: u8) -> Option<ProfileConfig> {
+    CONFIGS[profile as usize]
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go;
+    use crate::prelude::*;
+
+    fn workers(profile: u8) -> Option<usize> {
+        diagnostics()
+            .into_iter()
+            .find(|d| d.profile == profile)
+            .and_then(|d| d.workers)
+    }
+
+    #[tokio::test]
+    async fn configures_listed_profiles() {
+        configure_from(GoConfig {
+            profiles: vec![
+                ProfileConfig {
+                    profile: 42,
+                    name: Some("io".to_string()),
+                    worker_threads: Some(1),
+                    timeout_ms: Some(50),
+                    ..Default::default()
+                },
+                ProfileConfig {
+                    profile: 43,
+                    worker_threads: Some(3),
+                    max_outstanding: Some(7),
+                    ..Default::default()
+                },
+            ],
+        });
+        assert_eq!(workers(42), Some(1));
+        assert_eq!(workers(43), Some(3));
+        let io = profile_config(42).unwrap();
+        assert_eq!(io.name.as_deref(), Some("io"));
+        let r = go!(
+            |tx: Sender<()>| async move {
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(());
+            },
+            io.context()
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        assert_eq!(profile_config(43).unwrap().max_outstanding, Some(7));
+        assert_eq!(profile_config(44), None);
+    }
+
+    #[test]
+    fn survives_a_poisoned_config_slot() {
+        let poisoner = std::thread::spawn(|| {
+            let _w = super::CONFIGS[70].write().unwrap();
+            panic!("poison profile 70");
+        });
+        assert!(poisoner.join().is_err());
+        configure_from(GoConfig {
+            profiles: vec![ProfileConfig {
+                profile: 70,
+                worker_threads: Some(1),
+                ..Default::default()
+            }],
+        });
+        assert_eq!(profile_config(70).unwrap().worker_threads, Some(1));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn config_deserializes_with_defaults() {
+        let config: GoConfig = serde_json::from_str(
+            r#"{"profiles": [{"profile": 3, "name": "cpu", "worker_threads": 2}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.profiles,
+            vec![ProfileConfig {
+                profile: 3,
+                name: Some("cpu".to_string()),
+                worker_threads: Some(2),
+                ..Default::default()
+            }]
+        );
+    }
+}
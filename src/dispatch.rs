@@ -0,0 +1,1783 @@
+use crate::autoscale::blocking_permit;
+use crate::cancel::{cancel_scope, CancelHandle};
+use crate::error::panic_message;
+use crate::hook::{observe_outcome, observer, report_panic, TaskObserver};
+use crate::prelude::*;
+use crate::profile::check_named;
+use crate::ratelimit::{take_token, throttle};
+use crate::stats::{
+    acquire_result_slot, record_outcome, track_blocking, track_labeled, OutstandingGuard,
+};
+use crate::trace::TaskTrace;
+use futures::{FutureExt, Stream};
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::pin::Pin;
+use std::sync::PoisonError;
+use tokio::sync::oneshot;
+use tokio::task::{AbortHandle, JoinError, JoinHandle};
+
+static STICKY_PROFILES: RwLock<Vec<u8>> = RwLock::new(Vec::new());
+
+/// spawn `f` once on `ctx.profile` and wait for its result, honouring `ctx.timeout` and
+/// `ctx.observe_global_shutdown`. a task that times out or is cancelled gets aborted.
+async fn attempt<T, F, Fut>(handle: &Handle, f: F, ctx: &Context) -> Result<T, GoError>
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_and_wait(handle, f, ctx, false).await.0
+}
+
+/// `attempt`, except that with `keep_late` a timed out task keeps running and the receiver
+/// for its late result is handed back
+async fn spawn_and_wait<T, F, Fut>(
+    handle: &Handle,
+    f: F,
+    ctx: &Context,
+    keep_late: bool,
+) -> (Result<T, GoError>, Option<oneshot::Receiver<T>>)
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    match spawn_task(handle, f, ctx).await {
+        Ok(task) => task.wait(ctx, keep_late).await,
+        Err(e) => (Err(e), None),
+    }
+}
+
+/// a task spawned by `spawn_task` whose result hasn't been waited for yet
+pub(crate) struct SpawnedTask<T> {
+    /// where the task runs, `ctx.fallback_profile` if the primary turned it away
+    profile: u8,
+    handle: Handle,
+    join: JoinHandle<()>,
+    receiver: oneshot::Receiver<T>,
+    end: TaskEnd,
+    poll_every: Option<Duration>,
+    _slot: OutstandingGuard,
+}
+
+/// who is told how a task's call ended: its profile's counters and observer, and its trace
+#[derive(Clone)]
+pub(crate) struct TaskEnd {
+    profile: u8,
+    observer: Option<Arc<dyn TaskObserver>>,
+    trace: TaskTrace,
+}
+
+impl TaskEnd {
+    pub(crate) fn new(profile: u8, trace: TaskTrace) -> Self {
+        TaskEnd {
+            profile,
+            observer: observer(profile),
+            trace,
+        }
+    }
+
+    /// record that the call ended with `r`
+    pub(crate) fn report<T>(&self, r: &Result<T, GoError>) {
+        record_outcome(self.profile, r);
+        if let Some(o) = &self.observer {
+            observe_outcome(o.as_ref(), self.profile, r);
+        }
+        self.trace.finished(r);
+    }
+}
+
+/// take a result slot on `ctx.profile` and spawn `f` there, without waiting for it, see
+/// `admit` for what comes before the spawn
+pub(crate) async fn spawn_task<T, F, Fut>(
+    handle: &Handle,
+    f: F,
+    ctx: &Context,
+) -> Result<SpawnedTask<T>, GoError>
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let (profile, handle, slot) = admit(handle, ctx).await?;
+    let (sender, receiver) = channel::<T>();
+    let trace = TaskTrace::new(profile, ctx);
+    let task = trace.instrument(cancel_scope(f(sender)));
+    let join = handle.spawn(track_labeled(profile, ctx.label.clone(), task));
+    Ok(SpawnedTask::new(
+        profile, handle, join, receiver, slot, trace,
+    ))
+}
+
+/// the profile, runtime and result slot a call for `ctx` runs with: a slot on `ctx.profile`,
+/// or on `ctx.fallback_profile` (in `RUNTIMES`) when the profile is draining or overloaded.
+/// under `set_rate_limit` this waits for a token first.
+async fn admit(handle: &Handle, ctx: &Context) -> Result<(u8, Handle, OutstandingGuard), GoError> {
+    check_named(ctx)?;
+    let (profile, handle, slot) = match acquire_result_slot(ctx.profile) {
+        Ok(slot) => (ctx.profile, handle.clone(), slot),
+        Err(e @ (GoError::Draining | GoError::Overloaded)) => match ctx.fallback_profile {
+            Some(fallback) if fallback != ctx.profile => {
+                let slot = acquire_result_slot(fallback)?;
+                (fallback, runtime_handle(fallback), slot)
+            }
+            _ => return Err(e),
+        },
+        Err(e) => return Err(e),
+    };
+    take_token(profile, ctx.fail_fast_on_rate_limit).await?;
+    Ok((profile, handle, slot))
+}
+
+/// `admit` for calls that spawn `task` right away instead of when first polled: the same
+/// checks of `ctx.profile`, without a fallback profile. a rate limit token is waited for by
+/// the returned task rather than the caller, see `throttle`.
+pub(crate) fn admit_now<F: Future>(
+    ctx: &Context,
+    task: F,
+) -> Result<(OutstandingGuard, impl Future<Output = F::Output>), GoError> {
+    check_named(ctx)?;
+    let slot = acquire_result_slot(ctx.profile)?;
+    let task = throttle(ctx.profile, ctx.fail_fast_on_rate_limit, task)?;
+    Ok((slot, task))
+}
+
+impl<T> SpawnedTask<T> {
+    /// a task spawned on `handle` that sends its result on `receiver`, telling the
+    /// profile's observer about it
+    pub(crate) fn new(
+        profile: u8,
+        handle: Handle,
+        join: JoinHandle<()>,
+        receiver: oneshot::Receiver<T>,
+        slot: OutstandingGuard,
+        trace: TaskTrace,
+    ) -> Self {
+        let end = TaskEnd::new(profile, trace);
+        if let Some(o) = &end.observer {
+            o.on_spawn(profile);
+        }
+        SpawnedTask {
+            profile,
+            handle,
+            join,
+            receiver,
+            end,
+            poll_every: None,
+            _slot: slot,
+        }
+    }
+}
+
+impl<T> SpawnedTask<T> {
+    /// handle to abort the task, it stays valid after `wait` has been dropped
+    pub(crate) fn abort_handle(&self) -> AbortHandle {
+        self.join.abort_handle()
+    }
+
+    /// what to tell once the call ends without `wait` having finished, see `go_all`
+    pub(crate) fn end(&self) -> TaskEnd {
+        self.end.clone()
+    }
+
+    /// also check the result channel with `try_recv` every `every` while waiting, see `go_poll`
+    pub(crate) fn poll_every(mut self, every: Duration) -> Self {
+        self.poll_every = Some(every);
+        self
+    }
+
+    /// wait for the result, see `spawn_and_wait`
+    pub(crate) async fn wait(
+        self,
+        ctx: &Context,
+        keep_late: bool,
+    ) -> (Result<T, GoError>, Option<oneshot::Receiver<T>>) {
+        self.wait_within(ctx, keep_late, None).await
+    }
+
+    /// `wait`, against `deadline` rather than a fresh `ctx.timeout` when given one
+    pub(crate) async fn wait_within(
+        mut self,
+        ctx: &Context,
+        keep_late: bool,
+        deadline: Option<&mut Deadline>,
+    ) -> (Result<T, GoError>, Option<oneshot::Receiver<T>>) {
+        let profile = self.profile;
+        let handle = &self.handle;
+        let join = &mut self.join;
+        let receiver = &mut self.receiver;
+        let poll_every = self.poll_every;
+        let wait = async {
+            let received = if ctx.observe_global_shutdown {
+                let token = shutdown_token();
+                tokio::select! {
+                    biased;
+                    msg = recv(receiver, poll_every) => msg,
+                    _ = token.cancelled() => return Err(GoError::Cancelled),
+                }
+            } else {
+                recv(receiver, poll_every).await
+            };
+            match received {
+                Ok(v) => Ok(v),
+                Err(_) => Err(sender_gone(profile, handle, join).await),
+            }
+        };
+        let r = match deadline {
+            Some(deadline) => deadline.within(wait).await,
+            None if ctx.timeout.is_zero() => Some(wait.await),
+            None => within_timeout(ctx, wait).await,
+        }
+        .unwrap_or(Err(GoError::Timeout { attempts: 1 }));
+        let mut late = None;
+        if let Err(e) = &r {
+            match e {
+                GoError::Timeout { .. } if keep_late => late = Some(self.receiver),
+                GoError::Timeout { .. } | GoError::Cancelled => self.join.abort(),
+                _ => {}
+            }
+        }
+        self.end.report(&r);
+        (r, late)
+    }
+}
+
+/// the value sent on `receiver`, `Err` once the sender is gone. with `poll_every` the channel
+/// is also checked with `try_recv` at that interval, in case a wakeup never arrives.
+async fn recv<T>(
+    receiver: &mut oneshot::Receiver<T>,
+    poll_every: Option<Duration>,
+) -> Result<T, ()> {
+    let every = match poll_every {
+        Some(every) => every,
+        None => return receiver.await.map_err(drop),
+    };
+    let mut tick = tokio::time::interval_at(tokio::time::Instant::now() + every, every);
+    loop {
+        tokio::select! {
+            biased;
+            msg = &mut *receiver => return msg.map_err(drop),
+            _ = tick.tick() => match receiver.try_recv() {
+                Ok(v) => return Ok(v),
+                Err(oneshot::error::TryRecvError::Closed) => return Err(()),
+                Err(oneshot::error::TryRecvError::Empty) => {}
+            },
+        }
+    }
+}
+
+/// tell a panic, an abort or a runtime shutdown from a plain dropped sender once the result
+/// channel closed. only called on the error path, so successful calls never wait on the
+/// `JoinHandle`.
+pub(crate) async fn sender_gone(
+    profile: u8,
+    handle: &Handle,
+    join: &mut JoinHandle<()>,
+) -> GoError {
+    match join.await {
+        Ok(()) => GoError::SenderDropped,
+        Err(e) => task_failure(profile, handle, e),
+    }
+}
+
+/// the error for a task on `handle` that ended with `e`: its panic (reported to the panic
+/// hooks), `GoError::RuntimeShutdown` when the runtime was shut down, else `GoError::Cancelled`
+pub(crate) fn task_failure(profile: u8, handle: &Handle, e: JoinError) -> GoError {
+    if e.is_panic() {
+        let message = panic_message(e.into_panic());
+        report_panic(profile, &message);
+        return GoError::Panicked(message);
+    }
+    // a runtime that has been shut down cancels whatever is spawned on it right away
+    match handle.spawn(async {}).now_or_never() {
+        Some(Err(e)) if e.is_cancelled() => GoError::RuntimeShutdown,
+        _ => GoError::Cancelled,
+    }
+}
+
+/// the output of `fut`, or `None` once `ctx.timeout` has passed on `ctx.clock`
+/// (tokio's timer when no clock is set)
+pub(crate) async fn within_timeout<F: Future>(ctx: &Context, fut: F) -> Option<F::Output> {
+    within(ctx, ctx.timeout, fut).await
+}
+
+/// the longest timeout tokio's timer wheel can represent (about 2.2 years); longer ones
+/// below `UNBOUNDED_TIMEOUT` are clamped to it
+pub const MAX_TIMEOUT: Duration = Duration::from_millis((1 << 36) - 1);
+
+/// timeouts of at least this long (about 136 years, e.g. `Duration::MAX`) mean "no
+/// timeout": the call waits for the result as if the timeout was 0, and the profile's
+/// `TaskObserver::on_timeout_ignored` is told
+pub const UNBOUNDED_TIMEOUT: Duration = Duration::from_secs(u32::MAX as u64);
+
+/// the limit to arm a timer with, `None` when `limit` is too large to mean anything
+pub(crate) fn timer_limit(limit: Duration) -> Option<Duration> {
+    if limit >= UNBOUNDED_TIMEOUT {
+        None
+    } else {
+        Some(limit.min(MAX_TIMEOUT))
+    }
+}
+
+/// tell that `limit` is too large for a timer, so a call on `ctx` waits without one
+fn timeout_ignored(ctx: &Context, limit: Duration) {
+    #[cfg(feature = "tracing")]
+    tracing::warn!(
+        profile = ctx.profile,
+        ?limit,
+        "timeout too large for a timer, waiting without one"
+    );
+    if let Some(o) = observer(ctx.profile) {
+        o.on_timeout_ignored(ctx.profile, limit);
+    }
+}
+
+/// `ctx.timeout` armed once, for a call that waits in stages all bound by the same limit
+pub(crate) struct Deadline(Option<Pin<Box<dyn Future<Output = ()> + Send>>>);
+
+impl Deadline {
+    /// a deadline `ctx.timeout` from now on `ctx.clock`, none for a timeout of 0
+    pub(crate) fn new(ctx: &Context) -> Self {
+        if ctx.timeout.is_zero() {
+            return Deadline(None);
+        }
+        let limit = match timer_limit(ctx.timeout) {
+            Some(limit) => limit,
+            None => {
+                timeout_ignored(ctx, ctx.timeout);
+                return Deadline(None);
+            }
+        };
+        Deadline(Some(match &ctx.clock {
+            None => Box::pin(tokio::time::sleep(limit)),
+            Some(clock) => clock.sleep(limit),
+        }))
+    }
+
+    /// the output of `fut`, or `None` once the deadline has passed
+    pub(crate) async fn within<F: Future>(&mut self, fut: F) -> Option<F::Output> {
+        match &mut self.0 {
+            None => Some(fut.await),
+            Some(timer) => tokio::select! {
+                biased;
+                v = fut => Some(v),
+                _ = timer.as_mut() => None,
+            },
+        }
+    }
+}
+
+/// `within_timeout` with a limit other than `ctx.timeout`
+pub(crate) async fn within<F: Future>(ctx: &Context, limit: Duration, fut: F) -> Option<F::Output> {
+    let limit = match timer_limit(limit) {
+        Some(limit) => limit,
+        None => {
+            timeout_ignored(ctx, limit);
+            return Some(fut.await);
+        }
+    };
+    match &ctx.clock {
+        None => tokio::time::timeout(limit, fut).await.ok(),
+        Some(clock) => tokio::select! {
+            biased;
+            v = fut => Some(v),
+            _ = clock.sleep(limit) => None,
+        },
+    }
+}
+
+/// run `f` a single time according to `ctx`; `ctx.auto_retry` is ignored as `f` can't be re-run
+#[doc(hidden)]
+pub async fn dispatch_once<T, F, Fut>(f: F, ctx: Context) -> Result<T, GoError>
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let handle = runtime_handle(ctx.profile);
+    attempt(&handle, f, &ctx).await
+}
+
+/// run `f` according to `ctx`, re-spawning it after a timeout while `ctx.auto_retry` allows
+#[doc(hidden)]
+pub async fn dispatch<T, F, Fut>(f: F, ctx: Context) -> Result<T, GoError>
+where
+    F: Fn(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    dispatch_on(runtime_handle(ctx.profile), f, ctx).await
+}
+
+/// `dispatch` on the runtime the caller is running on when this is called, instead of the
+/// profile's runtime in `RUNTIMES`; `GoError::NoRuntime` without one. `ctx.profile` still
+/// selects the counters, limits and hooks the call goes through.
+#[doc(hidden)]
+pub fn dispatch_here<T, F, Fut>(f: F, ctx: Context) -> impl Future<Output = Result<T, GoError>>
+where
+    F: Fn(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let handle = Handle::try_current();
+    async move {
+        match handle {
+            Ok(handle) => dispatch_on(handle, f, ctx).await,
+            Err(_) => Err(GoError::NoRuntime),
+        }
+    }
+}
+
+/// `dispatch` on the runtime behind `handle` rather than the profile's slot in `RUNTIMES`
+pub(crate) async fn dispatch_on<T, F, Fut>(handle: Handle, f: F, ctx: Context) -> Result<T, GoError>
+where
+    F: Fn(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let policy = ctx.auto_retry.unwrap_or_default();
+    let mut tried = 1;
+    loop {
+        let r = attempt(&handle, &f, &ctx).await;
+        if !retries_after(&r, tried, &policy) {
+            return match r {
+                Err(GoError::Timeout { .. }) => Err(GoError::Timeout { attempts: tried }),
+                r => r,
+            };
+        }
+        tried += 1;
+        if !policy.backoff.is_zero() {
+            sleep(policy.backoff).await;
+        }
+    }
+}
+
+/// whether attempt number `tried` ending with `r` gets another one under `policy`
+fn retries_after<T>(r: &Result<T, GoError>, tried: u32, policy: &RetryPolicy) -> bool {
+    matches!(r, Err(GoError::Timeout { .. })) && tried < policy.max_attempts
+}
+
+/// how one attempt of a retried call ended, see `go_retry_stream!`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttemptResult<T> {
+    /// 1 for the first attempt
+    pub attempt: u32,
+    pub result: Result<T, GoError>,
+    /// how long the attempt took, not counting the backoff before it
+    pub elapsed: Duration,
+}
+
+/// run `f` like `dispatch`, yielding the outcome of every attempt as it ends. the stream
+/// ends after the attempt that `dispatch` would have returned.
+#[doc(hidden)]
+pub fn retry_attempts<T, F, Fut>(f: F, ctx: Context) -> impl Stream<Item = AttemptResult<T>>
+where
+    F: Fn(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let handle = runtime_handle(ctx.profile);
+    let policy = ctx.auto_retry.unwrap_or_default();
+    let state = (Some(1), f, ctx, handle);
+    futures::stream::unfold(state, move |(next, f, ctx, handle)| async move {
+        let tried = next?;
+        if tried > 1 && !policy.backoff.is_zero() {
+            sleep(policy.backoff).await;
+        }
+        let start = std::time::Instant::now();
+        let result = attempt(&handle, &f, &ctx).await;
+        let next = retries_after(&result, tried, &policy).then_some(tried + 1);
+        let outcome = AttemptResult {
+            attempt: tried,
+            result,
+            elapsed: start.elapsed(),
+        };
+        Some((outcome, (next, f, ctx, handle)))
+    })
+}
+
+/// run `f` once according to `ctx`, also checking for the result every `poll_interval`
+/// instead of relying on the wakeup alone. this is a niche robustness option for bridges
+/// to foreign executors or wakers; with plain tokio the wakeup is never missed.
+#[doc(hidden)]
+pub async fn go_poll<T, F, Fut>(f: F, poll_interval: Duration, ctx: Context) -> Result<T, GoError>
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let handle = runtime_handle(ctx.profile);
+    match spawn_task(&handle, f, &ctx).await {
+        Ok(task) => task.poll_every(poll_interval).wait(&ctx, false).await.0,
+        Err(e) => Err(e),
+    }
+}
+
+/// run `f` once according to `ctx` unless `event` completes first, in which case the task
+/// is aborted and `GoError::Interrupted` returned. the event's output is ignored.
+#[doc(hidden)]
+pub async fn go_until<T, F, Fut, E>(f: F, event: E, ctx: Context) -> Result<T, GoError>
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+    E: Future,
+{
+    let handle = runtime_handle(ctx.profile);
+    let task = spawn_task(&handle, f, &ctx).await?;
+    wait_unless(task, event, GoError::Interrupted, &ctx).await
+}
+
+/// wait for `task` unless `event` completes first, then abort it and fail with `err`
+async fn wait_unless<T, E: Future>(
+    task: SpawnedTask<T>,
+    event: E,
+    err: GoError,
+    ctx: &Context,
+) -> Result<T, GoError> {
+    let (abort, end) = (task.abort_handle(), task.end());
+    tokio::select! {
+        biased;
+        (r, _) = task.wait(ctx, false) => r,
+        _ = event => {
+            abort.abort();
+            let r = Err(err);
+            end.report(&r);
+            r
+        }
+    }
+}
+
+/// run `f` once according to `ctx`, returning the call together with a handle that can
+/// abort it: `CancelHandle::cancel` makes the call return `GoError::Cancelled` promptly,
+/// unless the result was already sent. dropping the handle leaves the task running.
+pub fn go_cancelable<T, F, Fut>(
+    f: F,
+    ctx: Context,
+) -> (impl Future<Output = Result<T, GoError>>, CancelHandle)
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let cancel = CancelHandle::default();
+    let own = cancel.clone();
+    let call = async move {
+        if own.is_cancelled() {
+            return Err(GoError::Cancelled);
+        }
+        let handle = runtime_handle(ctx.profile);
+        let task = spawn_task(&handle, f, &ctx).await?;
+        own.attach(task.abort_handle());
+        wait_unless(task, own.token().cancelled(), GoError::Cancelled, &ctx).await
+    };
+    (call, cancel)
+}
+
+/// run `f` once according to `ctx`; when it times out, return `GoError::Timeout` together
+/// with a receiver for the result the task may still deliver, instead of aborting it.
+#[doc(hidden)]
+pub async fn go_hedged<T, F, Fut>(
+    f: F,
+    ctx: Context,
+) -> (Result<T, GoError>, Option<oneshot::Receiver<T>>)
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let handle = runtime_handle(ctx.profile);
+    spawn_and_wait(&handle, f, &ctx, true).await
+}
+
+/// spawn `f` on `ctx.profile` with a sender made by the caller, who awaits the matching
+/// receiver. `ctx.timeout` and `ctx.auto_retry` don't apply as nothing here waits for the result.
+/// fails without spawning, dropping `sender`, when the profile refuses new work (e.g.
+/// `GoError::Draining`); the task counts as outstanding until it ends.
+#[doc(hidden)]
+pub fn go_with_sender<T, F, Fut>(
+    sender: Sender<T>,
+    f: F,
+    ctx: Context,
+) -> Result<JoinHandle<()>, GoError>
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let (slot, task) = admit_now(&ctx, cancel_scope(f(sender)))?;
+    let trace = TaskTrace::new(ctx.profile, &ctx);
+    let task = trace.watch(task);
+    let task = track(ctx.profile, async move {
+        let _slot = slot;
+        task.await
+    });
+    Ok(runtime_handle(ctx.profile).spawn(task))
+}
+
+/// check at compile time that `f` can be passed to `go!`: it takes the result `Sender` and
+/// returns a `Send + 'static` future. a closure whose future holds something `!Send` (an
+/// `Rc`, a `MutexGuard` kept across an `.await`, ...) then fails right here at the call
+/// site instead of somewhere inside the spawn machinery.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// let f = |tx: Sender<i32>| async move {
+///     let _ = tx.send(1);
+/// };
+/// assert_go_compatible(&f);
+/// ```
+pub const fn assert_go_compatible<F, Fut, T>(_f: &F)
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+}
+
+/// run `f` according to `ctx`, running it again after a panic up to `max_restarts` times.
+/// the error of the last attempt is returned once the restarts are used up.
+#[doc(hidden)]
+pub async fn supervise<T, F, Fut>(f: F, max_restarts: u32, ctx: Context) -> Result<T, GoError>
+where
+    F: Fn(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let mut restarts = 0;
+    loop {
+        match dispatch(&f, ctx.clone()).await {
+            Err(GoError::Panicked(_)) if restarts < max_restarts => restarts += 1,
+            r => return r,
+        }
+    }
+}
+
+/// the function form of `go!`: run `f` on the default profile with no timeout. unlike the
+/// macro it takes any closure, function or boxed `FnOnce`, not just a closure literal.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// async fn answer(tx: Sender<i32>) {
+///     let _ = tx.send(42);
+/// }
+/// #[tokio::main]
+/// async fn main(){
+/// assert_eq!(go(answer).await, Ok(42));
+/// }
+/// ```
+pub async fn go<T, F, Fut>(f: F) -> Result<T, GoError>
+where
+    F: FnOnce(Sender<T>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    go_with(f, Context::default()).await
+}
+
+/// `go` honouring `ctx` like `go!(f, ctx)`, except for `ctx.auto_retry`: `f` is `FnOnce`
+/// so it can't be re-run, use `go!` to retry
+pub async fn go_with<T, F, Fut>(f: F, ctx: Context) -> Result<T, GoError>
+where
+    F: FnOnce(Sender<T>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    dispatch_once(f, ctx).await
+}
+
+/// the function form of `go!(async move { .. })`: run the future `f` builds on the default
+/// profile and return its output, no `Sender` needed
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// #[tokio::main]
+/// async fn main(){
+/// assert_eq!(go_value(|| async move { 42 }).await, Ok(42));
+/// }
+/// ```
+pub async fn go_value<T, F, Fut>(f: F) -> Result<T, GoError>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    go_value_with(f, Context::default()).await
+}
+
+/// `go_value` honouring `ctx` like `go_with`, `ctx.auto_retry` doesn't apply either
+pub async fn go_value_with<T, F, Fut>(f: F, ctx: Context) -> Result<T, GoError>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    dispatch_value(async move { f().await }, ctx).await
+}
+
+/// run `fut` once according to `ctx` and send its output back; `ctx.auto_retry` is ignored
+/// as a future can't be re-run
+#[doc(hidden)]
+pub async fn dispatch_value<T, Fut>(fut: Fut, ctx: Context) -> Result<T, GoError>
+where
+    Fut: Future<Output = T> + Send + 'static,
+    T: Send + 'static,
+{
+    dispatch_once(
+        |tx: Sender<T>| async move {
+            let _ = tx.send(fut.await);
+        },
+        ctx,
+    )
+    .await
+}
+
+/// run the blocking closure `f` on the blocking pool of `ctx.profile` and wait for its
+/// value, honouring `ctx` like `go_with`: it goes through the profile's limits, counters and
+/// hooks like any task, and waits for a slot first under `enable_blocking_autoscale`, within
+/// the same `ctx.timeout` as the closure itself. a closure running on the blocking pool can't be aborted, so after a timeout it still runs
+/// to completion in the background, its value dropped; only the caller stops waiting.
+/// `ctx.auto_retry` doesn't apply.
+#[doc(hidden)]
+pub async fn go_blocking<R, F>(f: F, ctx: Context) -> Result<R, GoError>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    let handle = runtime_handle(ctx.profile);
+    let (profile, handle, slot) = admit(&handle, &ctx).await?;
+    let trace = TaskTrace::new(profile, &ctx);
+    let mut deadline = Deadline::new(&ctx);
+    let permit = match deadline.within(blocking_permit(profile)).await {
+        Some(permit) => permit,
+        None => {
+            let r = Err(GoError::Timeout { attempts: 1 });
+            TaskEnd::new(profile, trace).report(&r);
+            return r;
+        }
+    };
+    let (sender, receiver) = channel::<R>();
+    let job = trace.blocking(move || {
+        let _permit = permit;
+        let _ = sender.send(f());
+    });
+    let join = handle.spawn_blocking(track_blocking(profile, ctx.label.clone(), job));
+    SpawnedTask::new(profile, handle, join, receiver, slot, trace)
+        .wait_within(&ctx, false, Some(&mut deadline))
+        .await
+        .0
+}
+
+/// run an already built future on `ctx.profile` and wait for it to finish, honouring
+/// `ctx.timeout`. any `Send + 'static` future works, including ones from runtime agnostic
+/// crates. tokio timers or IO inside it need the profile's runtime to have those drivers
+/// enabled (`enable_all`), which is the case for runtimes created by `init_runtime`.
+pub async fn go_fn<Fut>(fut: Fut, ctx: Context) -> Result<(), GoError>
+where
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    dispatch_once(
+        |tx: Sender<()>| async move {
+            fut.await;
+            let _ = tx.send(());
+        },
+        ctx,
+    )
+    .await
+}
+
+/// run `f` once on whichever of `profiles` has the fewest tasks in flight when the returned
+/// future is first polled
+#[doc(hidden)]
+pub async fn go_balanced<T, F, Fut>(profiles: &[u8], f: F) -> Result<T, GoError>
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let ctx = Context {
+        profile: least_loaded(profiles),
+        ..Default::default()
+    };
+    dispatch_once(f, ctx).await
+}
+
+/// the profiles `go_sticky!` spreads keys over. changing the set remaps keys.
+pub fn set_sticky_profiles(profiles: &[u8]) {
+    *STICKY_PROFILES
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = profiles.to_vec();
+}
+
+/// the profile `key` is routed to by `go_sticky!`: always the same one for equal keys as long
+/// as the set from `set_sticky_profiles` doesn't change. profile 0 if no set was configured.
+pub fn sticky_profile<K: Hash + ?Sized>(key: &K) -> u8 {
+    let profiles = STICKY_PROFILES
+        .read()
+        .unwrap_or_else(PoisonError::into_inner);
+    if profiles.is_empty() {
+        return 0;
+    }
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    profiles[(hasher.finish() % profiles.len() as u64) as usize]
+}
+
+/// run `f` once on the profile `key` hashes to, see `sticky_profile`
+#[doc(hidden)]
+pub async fn go_sticky<K, T, F, Fut>(key: &K, f: F) -> Result<T, GoError>
+where
+    K: Hash + ?Sized,
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let ctx = Context {
+        profile: sticky_profile(key),
+        ..Default::default()
+    };
+    dispatch_once(f, ctx).await
+}
+
+/// spawn `f` on `profile` without waiting for it; with the `tracing` feature a resulting
+/// `GoError` is logged at `error` level. the profile's observer and panic hook hear about
+/// it either way, like for any call; nothing is written anywhere else.
+/// the returned handle only resolves once the outcome is known and can simply be dropped.
+#[doc(hidden)]
+pub fn go_log_err<T, F, Fut>(profile: u8, f: F) -> tokio::task::JoinHandle<()>
+where
+    F: FnOnce(Sender<T>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let ctx = Context {
+        profile,
+        ..Default::default()
+    };
+    let watch = async move {
+        let r = dispatch_once(f, ctx).await;
+        #[cfg(feature = "tracing")]
+        if let Err(e) = r {
+            tracing::error!(profile, error = %e, "detached go task failed");
+        }
+        #[cfg(not(feature = "tracing"))]
+        let _ = r;
+    };
+    #[cfg(feature = "tracing")]
+    let watch = tracing::instrument::WithSubscriber::with_current_subscriber(watch);
+    runtime_handle(profile).spawn(watch)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go;
+    use crate::go_poll;
+    use crate::go_retry_stream;
+    use crate::go_until;
+    use crate::prelude::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn auto_retry_after_timeout() {
+        let calls = AtomicU32::new(0);
+        let r = go!(
+            |tx: Sender<u32>| {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if n == 0 {
+                        sleep(Duration::from_secs(5)).await;
+                    }
+                    let _ = tx.send(n);
+                }
+            },
+            Context {
+                profile: 13,
+                timeout: Duration::from_millis(100),
+                auto_retry: Some(RetryPolicy {
+                    max_attempts: 3,
+                    backoff: Duration::from_millis(10),
+                }),
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r, Ok(1));
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn builder_retries_until_success_or_exhaustion() {
+        let ctx = Context::profile(61)
+            .timeout(Duration::from_millis(50))
+            .retries(3)
+            .backoff(Duration::from_millis(5));
+        let calls = AtomicU32::new(0);
+        let r = go!(
+            |tx: Sender<u32>| {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if n < 2 {
+                        sleep(Duration::from_secs(5)).await;
+                    }
+                    let _ = tx.send(n);
+                }
+            },
+            ctx.clone()
+        )
+        .await;
+        assert_eq!(r, Ok(2));
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        let calls = AtomicU32::new(0);
+        let r = go!(
+            |tx: Sender<u32>| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    sleep(Duration::from_secs(5)).await;
+                    let _ = tx.send(0);
+                }
+            },
+            ctx.clone()
+        )
+        .await;
+        let e = r.unwrap_err();
+        assert_eq!(e, GoError::Timeout { attempts: 4 });
+        assert_eq!(e.to_string(), "timeout after 4 attempts");
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+
+        let calls = AtomicU32::new(0);
+        let r = go!(
+            |tx: Sender<u32>| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move { drop(tx) }
+            },
+            ctx
+        )
+        .await;
+        assert_eq!(r, Err(GoError::SenderDropped));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn retry_stream_yields_every_attempt() {
+        use futures::StreamExt;
+        let calls = AtomicU32::new(0);
+        let ctx = Context {
+            profile: 45,
+            timeout: Duration::from_millis(50),
+            auto_retry: Some(RetryPolicy {
+                max_attempts: 4,
+                backoff: Duration::ZERO,
+            }),
+            ..Default::default()
+        };
+        let attempts: Vec<_> = go_retry_stream!(
+            |tx: Sender<u32>| {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if n < 2 {
+                        sleep(Duration::from_secs(5)).await;
+                    }
+                    let _ = tx.send(n);
+                }
+            },
+            ctx.clone()
+        )
+        .collect()
+        .await;
+        let outcomes: Vec<_> = attempts
+            .iter()
+            .map(|a| (a.attempt, a.result.clone()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            vec![
+                (1, Err(GoError::Timeout { attempts: 1 })),
+                (2, Err(GoError::Timeout { attempts: 1 })),
+                (3, Ok(2))
+            ]
+        );
+        assert!(attempts[0].elapsed >= Duration::from_millis(50));
+
+        let attempts: Vec<_> = go_retry_stream!(
+            |tx: Sender<u32>| async move {
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(0);
+            },
+            ctx
+        )
+        .collect()
+        .await;
+        assert_eq!(attempts.len(), 4);
+        assert!(attempts
+            .iter()
+            .all(|a| a.result == Err(GoError::Timeout { attempts: 1 })));
+    }
+
+    #[tokio::test]
+    async fn poll_fallback_resolves_on_wakeup() {
+        let start = std::time::Instant::now();
+        let r = go_poll!(
+            |tx: Sender<i32>| async move {
+                sleep(Duration::from_millis(20)).await;
+                let _ = tx.send(1);
+            },
+            Duration::from_secs(10),
+            Context {
+                profile: 46,
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r, Ok(1));
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let r = go_poll!(
+            |tx: Sender<i32>| async move { drop(tx) },
+            Duration::from_millis(5)
+        )
+        .await;
+        assert_eq!(r, Err(GoError::SenderDropped));
+    }
+
+    #[tokio::test]
+    async fn draining_primary_falls_back() {
+        fn spawned_on(profile: u8) -> u64 {
+            diagnostics()
+                .into_iter()
+                .find(|d| d.profile == profile)
+                .map_or(0, |d| d.spawned)
+        }
+        set_draining(51, true);
+        let ctx = Context {
+            profile: 51,
+            fallback_profile: Some(52),
+            ..Default::default()
+        };
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(1);
+            },
+            ctx.clone()
+        )
+        .await;
+        assert_eq!(r, Ok(1));
+        assert_eq!((spawned_on(51), spawned_on(52)), (0, 1));
+        set_draining(52, true);
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(1);
+            },
+            ctx
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Draining));
+    }
+
+    #[tokio::test]
+    async fn cancel_resolves_call_promptly() {
+        use crate::go_cancelable;
+        use std::sync::atomic::AtomicBool;
+        let finished = Arc::new(AtomicBool::new(false));
+        let flag = finished.clone();
+        let (call, cancel) = go_cancelable!(
+            |tx: Sender<i32>| async move {
+                sleep(Duration::from_secs(10)).await;
+                flag.store(true, Ordering::SeqCst);
+                let _ = tx.send(1);
+            },
+            Context {
+                profile: 58,
+                timeout: Duration::from_secs(20),
+                ..Default::default()
+            }
+        );
+        let start = std::time::Instant::now();
+        let canceller = cancel.clone();
+        tokio::spawn(async move {
+            sleep(Duration::from_millis(50)).await;
+            canceller.cancel();
+        });
+        assert_eq!(call.await, Err(GoError::Cancelled));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert!(cancel.is_cancelled());
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(in_flight(58), 0);
+        assert!(!finished.load(Ordering::SeqCst));
+
+        let (call, cancel) = go_cancelable!(|tx: Sender<i32>| async move {
+            let _ = tx.send(1);
+            sleep(Duration::from_secs(10)).await;
+        });
+        let call = tokio::spawn(call);
+        sleep(Duration::from_millis(20)).await;
+        cancel.cancel();
+        assert_eq!(call.await.unwrap(), Ok(1));
+
+        let (call, cancel) = go_cancelable!(|tx: Sender<i32>| async move {
+            sleep(Duration::from_millis(20)).await;
+            let _ = tx.send(2);
+        });
+        drop(cancel);
+        assert_eq!(call.await, Ok(2));
+    }
+
+    #[tokio::test]
+    async fn until_aborts_on_event() {
+        use std::sync::atomic::AtomicBool;
+        let finished = Arc::new(AtomicBool::new(false));
+        let flag = finished.clone();
+        let (disconnect, disconnected) = channel::<()>();
+        let event = async move {
+            sleep(Duration::from_millis(50)).await;
+            let _ = disconnect.send(());
+        };
+        let call = go_until!(
+            |tx: Sender<i32>| async move {
+                sleep(Duration::from_millis(300)).await;
+                flag.store(true, Ordering::SeqCst);
+                let _ = tx.send(1);
+            },
+            disconnected,
+            Context {
+                profile: 54,
+                ..Default::default()
+            }
+        );
+        let (r, _) = tokio::join!(call, event);
+        assert_eq!(r, Err(GoError::Interrupted));
+        sleep(Duration::from_millis(400)).await;
+        assert!(!finished.load(Ordering::SeqCst));
+        assert_eq!(in_flight(54), 0);
+
+        let r = go_until!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(2);
+            },
+            std::future::pending::<()>()
+        )
+        .await;
+        assert_eq!(r, Ok(2));
+    }
+
+    #[tokio::test]
+    async fn no_retry_without_policy() {
+        let calls = AtomicU32::new(0);
+        let r = go!(
+            |tx: Sender<u32>| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    sleep(Duration::from_secs(5)).await;
+                    let _ = tx.send(0);
+                }
+            },
+            Context {
+                profile: 13,
+                timeout: Duration::from_millis(50),
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn value_functions_return_the_output() {
+        assert_eq!(go_value(|| async move { 42 }).await, Ok(42));
+        let name = "go".to_string();
+        let r = go_value_with(
+            move || async move { format!("{}-{}", name, 1) },
+            Context::profile(2),
+        )
+        .await;
+        assert_eq!(r, Ok("go-1".to_string()));
+        let r = go_value_with(
+            || async move { sleep(Duration::from_secs(5)).await },
+            Context::default().timeout(Duration::from_millis(20)),
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+    }
+
+    #[tokio::test]
+    async fn blocking_closures_time_out_without_blocking_the_caller() {
+        use crate::{go, go_blocking};
+        init_runtime_with(
+            64,
+            RuntimeConfig {
+                worker_threads: Some(1),
+                thread_name_prefix: Some("blk".to_string()),
+                ..Default::default()
+            },
+        );
+        let slow = || {
+            std::thread::sleep(Duration::from_millis(200));
+            std::thread::current().name().map(str::to_string)
+        };
+        let ctx = Context::profile(64);
+        let blocking_thread = go_blocking!(slow, ctx.clone()).await.unwrap().unwrap();
+        let worker_thread = go!(
+            |tx: Sender<Option<String>>| async move {
+                let _ = tx.send(std::thread::current().name().map(str::to_string));
+            },
+            ctx.clone()
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert!(blocking_thread.starts_with("blk-"));
+        assert_ne!(blocking_thread, worker_thread);
+
+        let start = std::time::Instant::now();
+        let r = go_blocking!(slow, ctx.clone().timeout(Duration::from_millis(50))).await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        assert!(start.elapsed() < Duration::from_millis(150));
+        assert_eq!(in_flight(64), 1);
+        sleep(Duration::from_millis(250)).await;
+        assert_eq!(in_flight(64), 0);
+
+        #[derive(Default)]
+        struct Spawns(AtomicU32);
+        impl TaskObserver for Spawns {
+            fn on_spawn(&self, _profile: u8) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        let spawns = Arc::new(Spawns::default());
+        set_observer(64, spawns.clone());
+        assert_eq!(go_blocking!(|| 1, ctx.clone()).await, Ok(1));
+        assert_eq!(spawns.0.load(Ordering::SeqCst), 1);
+        set_draining(64, true);
+        assert_eq!(
+            go_blocking!(|| 1, ctx.clone()).await,
+            Err(GoError::Draining)
+        );
+        set_draining(64, false);
+        clear_observer(64);
+        assert_eq!(spawns.0.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn go_blocking_waits_for_a_slot_within_its_timeout() {
+        use crate::go_blocking;
+        enable_blocking_autoscale(83, 1, 1);
+        let ctx = Context::profile(83).timeout(Duration::from_millis(200));
+        let sleep_for = |ms| move || std::thread::sleep(Duration::from_millis(ms));
+        let holder = tokio::spawn(go_blocking!(sleep_for(400), ctx.clone()));
+        sleep(Duration::from_millis(20)).await;
+        let r = go_blocking!(sleep_for(0), ctx.clone().timeout(Duration::from_millis(50))).await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        assert_eq!(last_error(83), Some(GoError::Timeout { attempts: 1 }));
+        assert_eq!(holder.await.unwrap(), Err(GoError::Timeout { attempts: 1 }));
+        // its closure keeps the slot until it returns
+        sleep(Duration::from_millis(250)).await;
+
+        // the slot frees up after 150ms, the closure would need 150ms more
+        let holder = tokio::spawn(go_blocking!(sleep_for(150), Context::profile(83)));
+        sleep(Duration::from_millis(20)).await;
+        let start = std::time::Instant::now();
+        let r = go_blocking!(sleep_for(150), ctx.clone()).await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        assert!(start.elapsed() < Duration::from_millis(250));
+        assert_eq!(holder.await.unwrap(), Ok(()));
+        disable_blocking_autoscale(83);
+    }
+
+    #[tokio::test]
+    async fn functions_match_the_macro() {
+        use std::future::Future;
+        use std::pin::Pin;
+        type Job = Box<dyn FnOnce(Sender<i32>) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send>;
+        struct Later {
+            job: Job,
+        }
+        async fn send_one(tx: Sender<i32>) {
+            let _ = tx.send(1);
+        }
+        async fn hang(tx: Sender<i32>) {
+            sleep(Duration::from_secs(5)).await;
+            let _ = tx.send(1);
+        }
+        async fn give_up(tx: Sender<i32>) {
+            drop(tx);
+        }
+        let timeout = Context {
+            timeout: Duration::from_millis(20),
+            ..Default::default()
+        };
+
+        let later = Later {
+            job: Box::new(|tx| Box::pin(send_one(tx))),
+        };
+        assert_eq!(go(later.job).await, Ok(1));
+        assert_eq!(go(send_one).await, Ok(1));
+        assert_eq!(go!(|tx: Sender<i32>| send_one(tx)).await, Ok(1));
+
+        assert_eq!(
+            go_with(hang, timeout.clone()).await,
+            Err(GoError::Timeout { attempts: 1 })
+        );
+        assert_eq!(
+            go!(|tx: Sender<i32>| hang(tx), timeout.clone()).await,
+            Err(GoError::Timeout { attempts: 1 })
+        );
+
+        assert_eq!(go(give_up).await, Err(GoError::SenderDropped));
+        assert_eq!(
+            go_with(give_up, timeout.clone()).await,
+            Err(GoError::SenderDropped)
+        );
+        assert_eq!(
+            go!(|tx: Sender<i32>| give_up(tx)).await,
+            Err(GoError::SenderDropped)
+        );
+        assert_eq!(
+            go!(|tx: Sender<i32>| give_up(tx), timeout).await,
+            Err(GoError::SenderDropped)
+        );
+    }
+
+    #[tokio::test]
+    async fn here_spawns_on_the_callers_runtime() {
+        use crate::go_here;
+        let test_thread = std::thread::current().id();
+        let r = go_here!(
+            |tx: Sender<std::thread::ThreadId>| async move {
+                let _ = tx.send(std::thread::current().id());
+            },
+            67
+        )
+        .await;
+        assert_eq!(r, Ok(test_thread));
+        assert!(RUNTIMES.read_slots()[67].is_none());
+        let r = go_here!(
+            |tx: Sender<()>| async move {
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(());
+            },
+            Context::profile(67).timeout(Duration::from_millis(20))
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+    }
+
+    #[test]
+    fn here_without_runtime_fails() {
+        use crate::go_here;
+        let call = go_here!(|tx: Sender<i32>| async move {
+            let _ = tx.send(1);
+        });
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        assert_eq!(runtime.block_on(call), Err(GoError::NoRuntime));
+    }
+
+    #[tokio::test]
+    async fn go_fn_runs_plain_futures() {
+        use std::sync::atomic::AtomicBool;
+        use std::task::Poll;
+        let ran = Arc::new(AtomicBool::new(false));
+        let flag = ran.clone();
+        let ctx = Context {
+            profile: 30,
+            ..Default::default()
+        };
+        let r = go_fn(
+            async move {
+                flag.store(true, Ordering::SeqCst);
+            },
+            ctx.clone(),
+        )
+        .await;
+        assert_eq!(r, Ok(()));
+        assert!(ran.load(Ordering::SeqCst));
+
+        let mut polls = 0;
+        let hand_rolled = futures::future::poll_fn(move |cx| {
+            polls += 1;
+            if polls < 3 {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            } else {
+                Poll::Ready(())
+            }
+        });
+        assert_eq!(go_fn(hand_rolled, ctx).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn sticky_routes_key_to_same_profile() {
+        use crate::go_sticky;
+        set_sticky_profiles(&[25, 26, 27]);
+        let profile = sticky_profile("user-42");
+        assert!([25, 26, 27].contains(&profile));
+        for _ in 0..5 {
+            let before = diagnostics()
+                .iter()
+                .find(|d| d.profile == profile)
+                .map_or(0, |d| d.spawned);
+            let r = go_sticky!("user-42", |tx: Sender<()>| async move {
+                let _ = tx.send(());
+            })
+            .await;
+            assert_eq!(r, Ok(()));
+            assert_eq!(sticky_profile("user-42"), profile);
+            let after = diagnostics()
+                .into_iter()
+                .find(|d| d.profile == profile)
+                .unwrap();
+            assert_eq!(after.spawned, before + 1);
+        }
+    }
+
+    #[test]
+    fn oversized_timeouts_are_clamped_or_dropped() {
+        use super::timer_limit;
+        assert_eq!(
+            timer_limit(Duration::from_secs(1)),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            timer_limit(MAX_TIMEOUT + Duration::from_secs(1)),
+            Some(MAX_TIMEOUT)
+        );
+        assert_eq!(timer_limit(UNBOUNDED_TIMEOUT), None);
+        assert_eq!(timer_limit(Duration::MAX), None);
+    }
+
+    #[tokio::test]
+    async fn max_duration_means_no_timeout() {
+        #[derive(Default)]
+        struct Ignored(AtomicU32);
+        impl TaskObserver for Ignored {
+            fn on_timeout_ignored(&self, _profile: u8, timeout: Duration) {
+                assert_eq!(timeout, Duration::MAX);
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        let ignored = Arc::new(Ignored::default());
+        set_observer(41, ignored.clone());
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                sleep(Duration::from_millis(50)).await;
+                let _ = tx.send(1);
+            },
+            Context {
+                profile: 41,
+                timeout: Duration::MAX,
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r, Ok(1));
+        assert_eq!(ignored.0.load(Ordering::SeqCst), 1);
+        clear_observer(41);
+    }
+
+    #[tokio::test]
+    async fn observer_sees_lifecycle() {
+        use std::sync::Mutex;
+        #[derive(Default)]
+        struct Recorder(Mutex<Vec<String>>);
+        impl TaskObserver for Recorder {
+            fn on_spawn(&self, profile: u8) {
+                self.0.lock().unwrap().push(format!("spawn {}", profile));
+            }
+            fn on_complete(&self, profile: u8) {
+                self.0.lock().unwrap().push(format!("complete {}", profile));
+            }
+            fn on_timeout(&self, profile: u8) {
+                self.0.lock().unwrap().push(format!("timeout {}", profile));
+            }
+        }
+        let recorder = Arc::new(Recorder::default());
+        set_observer(33, recorder.clone());
+        let ctx = Context {
+            profile: 33,
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let r = go!(
+            |tx: Sender<()>| async move {
+                let _ = tx.send(());
+            },
+            ctx.clone()
+        )
+        .await;
+        assert_eq!(r, Ok(()));
+        assert_eq!(*recorder.0.lock().unwrap(), vec!["spawn 33", "complete 33"]);
+        let r = go!(
+            |tx: Sender<()>| async move {
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(());
+            },
+            ctx
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec!["spawn 33", "complete 33", "spawn 33", "timeout 33"]
+        );
+        clear_observer(33);
+    }
+
+    #[tokio::test]
+    async fn hedged_late_result_still_arrives() {
+        use crate::go_hedged;
+        let ctx = Context {
+            profile: 34,
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let (r, late) = go_hedged!(
+            |tx: Sender<&str>| async move {
+                sleep(Duration::from_millis(150)).await;
+                let _ = tx.send("late");
+            },
+            ctx.clone()
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        assert_eq!(late.unwrap().await.unwrap(), "late");
+
+        let (r, late) = go_hedged!(
+            |tx: Sender<&str>| async move {
+                let _ = tx.send("fast");
+            },
+            ctx
+        )
+        .await;
+        assert_eq!(r, Ok("fast"));
+        assert!(late.is_none());
+    }
+
+    #[tokio::test]
+    async fn external_sender_is_used() {
+        let (tx, rx) = channel::<String>();
+        let join = go!(
+            tx,
+            |sender: Sender<String>| async move {
+                let _ = sender.send("external".to_string());
+            },
+            Context {
+                profile: 24,
+                ..Default::default()
+            }
+        );
+        assert_eq!(rx.await.unwrap(), "external");
+        join.unwrap().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn supervise_restarts_after_panics() {
+        use crate::supervise;
+        use std::sync::Mutex;
+        static PANICS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+        set_panic_hook(|profile, msg| {
+            if profile == 23 {
+                PANICS.lock().unwrap().push(msg.to_string());
+            }
+        });
+        let calls = AtomicU32::new(0);
+        let ctx = Context {
+            profile: 23,
+            ..Default::default()
+        };
+        let r = supervise!(
+            |tx: Sender<u32>| {
+                let n = calls.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if n < 2 {
+                        panic!("boom {}", n);
+                    }
+                    let _ = tx.send(n);
+                }
+            },
+            3,
+            ctx.clone()
+        )
+        .await;
+        assert_eq!(r, Ok(2));
+        assert_eq!(*PANICS.lock().unwrap(), vec!["boom 0", "boom 1"]);
+
+        let r = supervise!(
+            |tx: Sender<u32>| async move {
+                drop(tx);
+                panic!("always");
+            },
+            1,
+            ctx
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Panicked("always".to_string())));
+        assert_eq!(PANICS.lock().unwrap().len(), 4);
+    }
+
+    #[tokio::test]
+    async fn balanced_routes_to_less_busy_profile() {
+        use crate::go_balanced;
+        let release = Arc::new(tokio::sync::Notify::new());
+        let wait = release.clone();
+        let busy = go!(
+            |tx: Sender<()>| {
+                let wait = wait.clone();
+                async move {
+                    wait.notified().await;
+                    let _ = tx.send(());
+                }
+            },
+            Context {
+                profile: 17,
+                ..Default::default()
+            }
+        );
+        let probe = async {
+            while in_flight(17) == 0 {
+                sleep(Duration::from_millis(5)).await;
+            }
+            assert_eq!(least_loaded(&[17, 18]), 18);
+            let r = go_balanced!(&[17, 18], |tx: Sender<u64>| async move {
+                let _ = tx.send(in_flight(18));
+            })
+            .await;
+            release.notify_one();
+            r
+        };
+        let (busy, r) = tokio::join!(busy, probe);
+        assert_eq!(busy, Ok(()));
+        assert_eq!(r, Ok(1));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn tasks_run_in_a_go_task_span() {
+        use crate::test_util::capture_logs;
+        use tracing::Instrument;
+
+        let (logs, _guard) = capture_logs();
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                tracing::info!("inside the task");
+                let _ = tx.send(1);
+            },
+            Context::profile(69).timeout(Duration::from_secs(1))
+        )
+        .instrument(tracing::info_span!("request"))
+        .await;
+        assert_eq!(r, Ok(1));
+        let logged = logs.contents();
+        assert!(
+            logged.contains("request:go_task{profile=69 timeout_ms=1000}"),
+            "{}",
+            logged
+        );
+        assert!(logged.contains("go task finished"), "{}", logged);
+        assert!(logged.contains("outcome=\"ok\""), "{}", logged);
+        assert!(logged.contains("elapsed_ms="), "{}", logged);
+        assert!(!logged.contains("WARN"), "{}", logged);
+
+        let r = go!(
+            |tx: Sender<()>| async move {
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(());
+            },
+            Context::profile(69).timeout(Duration::from_millis(20))
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        let logged = logs.contents();
+        let warned = logged
+            .lines()
+            .find(|l| l.contains("WARN"))
+            .unwrap_or_else(|| panic!("{}", logged));
+        assert!(
+            warned.contains("go_task{profile=69 timeout_ms=20}"),
+            "{}",
+            warned
+        );
+        assert!(warned.contains("outcome=\"timeout\""), "{}", warned);
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn every_entry_point_records_its_outcome() {
+        use crate::test_util::capture_logs;
+        use crate::{go_blocking, go_stream};
+        use futures::StreamExt;
+
+        let (logs, _guard) = capture_logs();
+        let ctx = Context::profile(75);
+        let outcomes = |logs: &crate::test_util::CapturedLogs| {
+            logs.contents()
+                .lines()
+                .filter(|l| l.contains("go_task{profile=75"))
+                .filter_map(|l| l.split("outcome=").nth(1))
+                .filter_map(|o| o.split_whitespace().next())
+                .map(|o| o.trim_matches('"').to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(go_blocking!(|| 1, ctx.clone()).await, Ok(1));
+        let items: Vec<i32> = go_stream!(
+            |tx: mpsc::Sender<i32>| async move {
+                let _ = tx.send(1).await;
+            },
+            ctx.clone()
+        )
+        .collect()
+        .await;
+        assert_eq!(items, vec![1]);
+        let (tx, rx) = channel::<i32>();
+        go_with_sender(
+            tx,
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(1);
+            },
+            ctx.clone(),
+        )
+        .unwrap()
+        .await
+        .unwrap();
+        assert_eq!(rx.await, Ok(1));
+        let r = go_until(
+            |tx: Sender<()>| async move {
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(());
+            },
+            sleep(Duration::from_millis(20)),
+            ctx.clone(),
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Interrupted));
+        let batch = go_map(0..2, |i| async move { i }, ctx.clone()).await;
+        assert!(batch.is_complete());
+        assert_eq!(
+            outcomes(&logs),
+            vec!["ok", "ok", "ok", "interrupted", "ok", "ok"],
+            "{}",
+            logs.contents()
+        );
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn log_err_reports_failed_detached_task() {
+        use crate::go_log_err;
+        use crate::test_util::capture_logs;
+
+        let (logs, _guard) = capture_logs();
+        let detached = go_log_err!(14, |tx: Sender<i32>| async move {
+            drop(tx);
+        });
+        detached.await.unwrap();
+        let logged = logs.contents();
+        assert!(logged.contains("ERROR"), "{}", logged);
+        assert!(logged.contains("sender dropped"), "{}", logged);
+    }
+}
@@ -0,0 +1,65 @@
+use std::any::Any;
+use std::fmt;
+
+/// error returned by `go!` and the functions built on the same dispatch path. new variants
+/// may be added, so matches on it need a wildcard arm
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum GoError {
+    /// the task did not send its result within `Context.timeout`, on any of the `attempts`
+    /// made (more than 1 with `Context.auto_retry`)
+    Timeout { attempts: u32 },
+    /// the task finished or was dropped without sending a result
+    SenderDropped,
+    /// the task panicked before sending a result, carries the panic message
+    Panicked(String),
+    /// the task was aborted before it finished
+    Cancelled,
+    /// the profile refused the call, e.g. because `set_max_outstanding` was reached
+    Overloaded,
+    /// the profile is draining (`set_draining`) and takes no new tasks
+    Draining,
+    /// the event passed to `go_until!` happened first, the task was aborted
+    Interrupted,
+    /// `set_rate_limit` was exceeded and the call had `Context.fail_fast_on_rate_limit` set
+    RateLimited,
+    /// the profile's runtime was shut down (`shutdown_runtime`) before the task finished
+    RuntimeShutdown,
+    /// `go_here!` was called outside of a tokio runtime
+    NoRuntime,
+    /// `Context.profile` is the slot of a named profile but the context wasn't made from the
+    /// name, see `init_runtime_named`
+    ReservedProfile,
+}
+
+impl fmt::Display for GoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GoError::Timeout { attempts: 1 } => write!(f, "timeout"),
+            GoError::Timeout { attempts } => write!(f, "timeout after {} attempts", attempts),
+            GoError::SenderDropped => write!(f, "sender dropped without sending a result"),
+            GoError::Panicked(msg) => write!(f, "task panicked: {}", msg),
+            GoError::Cancelled => write!(f, "task cancelled"),
+            GoError::Overloaded => write!(f, "profile overloaded"),
+            GoError::Draining => write!(f, "profile draining"),
+            GoError::Interrupted => write!(f, "task interrupted"),
+            GoError::RateLimited => write!(f, "profile rate limited"),
+            GoError::RuntimeShutdown => write!(f, "runtime shut down"),
+            GoError::NoRuntime => write!(f, "no tokio runtime to spawn on"),
+            GoError::ReservedProfile => write!(f, "profile reserved for a named profile"),
+        }
+    }
+}
+
+impl std::error::Error for GoError {}
+
+/// the message of a panic payload raised with a `&str` or `String`
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    match payload.downcast::<String>() {
+        Ok(s) => *s,
+        Err(payload) => match payload.downcast::<&'static str>() {
+            Ok(s) => s.to_string(),
+            Err(_) => "non-string panic payload".to_string(),
+        },
+    }
+}
@@ -0,0 +1,129 @@
+use crate::prelude::GoError;
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use std::time::Duration;
+use tokio::time::Timeout;
+
+/// combinators for the futures returned by `go!` and friends
+pub trait GoFutureExt<T>: Future<Output = Result<T, GoError>> + Sized {
+    /// fail with `GoError::Timeout` unless the result arrives within `d`.
+    /// unlike `Context.timeout` this only stops waiting, the spawned task keeps running.
+    fn timeout(self, d: Duration) -> WithTimeout<Self> {
+        WithTimeout {
+            inner: tokio::time::timeout(d, self),
+        }
+    }
+}
+
+impl<T, F> GoFutureExt<T> for F where F: Future<Output = Result<T, GoError>> {}
+
+pin_project! {
+    /// future returned by `GoFutureExt::timeout`
+    pub struct WithTimeout<F> {
+        #[pin]
+        inner: Timeout<F>,
+    }
+}
+
+impl<T, F> Future for WithTimeout<F>
+where
+    F: Future<Output = Result<T, GoError>>,
+{
+    type Output = Result<T, GoError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        match self.project().inner.poll(cx) {
+            Poll::Ready(Ok(r)) => Poll::Ready(r),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(GoError::Timeout { attempts: 1 })),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+pin_project! {
+    /// the future returned by `go!`. dropping it without ever polling it means the closure
+    /// never runs, which is almost always a forgotten `.await`; with the `tracing` feature
+    /// this logs a warning.
+    pub struct GoFuture<F> {
+        #[pin]
+        inner: F,
+        polled: bool,
+    }
+
+    impl<F> PinnedDrop for GoFuture<F> {
+        fn drop(this: Pin<&mut Self>) {
+            #[cfg(feature = "tracing")]
+            if !this.polled {
+                tracing::warn!("go! future dropped without being awaited, its closure never ran");
+            }
+            #[cfg(not(feature = "tracing"))]
+            let _ = this;
+        }
+    }
+}
+
+impl<F> GoFuture<F> {
+    #[doc(hidden)]
+    pub fn new(inner: F) -> Self {
+        GoFuture {
+            inner,
+            polled: false,
+        }
+    }
+}
+
+impl<F: Future> Future for GoFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<F::Output> {
+        let this = self.project();
+        *this.polled = true;
+        this.inner.poll(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go;
+    use crate::prelude::*;
+
+    #[tokio::test]
+    async fn timeout_combinator() {
+        let r = go!(|tx: Sender<i32>| async move {
+            sleep(Duration::from_millis(10)).await;
+            let _ = tx.send(1);
+        })
+        .timeout(Duration::from_secs(2))
+        .await;
+        assert_eq!(r, Ok(1));
+
+        let r = go!(|tx: Sender<i32>| async move {
+            sleep(Duration::from_secs(5)).await;
+            let _ = tx.send(1);
+        })
+        .timeout(Duration::from_millis(20))
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn unawaited_future_warns() {
+        use crate::test_util::capture_logs;
+        let (logs, _guard) = capture_logs();
+        let awaited = go!(|tx: Sender<i32>| async move {
+            let _ = tx.send(1);
+        });
+        assert_eq!(awaited.await, Ok(1));
+        assert!(!logs.contents().contains("WARN"));
+        let forgotten = go!(|tx: Sender<i32>| async move {
+            let _ = tx.send(1);
+        });
+        drop(forgotten);
+        let logged = logs.contents();
+        assert!(logged.contains("WARN"), "{}", logged);
+        assert!(logged.contains("without being awaited"), "{}", logged);
+    }
+}
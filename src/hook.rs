@@ -0,0 +1,212 @@
+use crate::prelude::{Duration, GoError};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::SystemTime;
+
+type PanicHook = Arc<dyn Fn(u8, &str) + Send + Sync>;
+
+static PANIC_HOOK: RwLock<Option<PanicHook>> = RwLock::new(None);
+
+/// call `hook` with the profile and panic message whenever a task spawned through the
+/// dispatch path is found to have panicked. replaces any previous hook.
+pub fn set_panic_hook<F>(hook: F)
+where
+    F: Fn(u8, &str) + Send + Sync + 'static,
+{
+    *PANIC_HOOK.write().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(hook));
+}
+
+/// remove the hook installed with `set_panic_hook`
+pub fn clear_panic_hook() {
+    *PANIC_HOOK.write().unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+/// how many panics `recent_panics` keeps by default
+pub const DEFAULT_PANIC_BUFFER_CAPACITY: usize = 64;
+
+/// a panic seen on the dispatch path, see `recent_panics`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanicRecord {
+    pub profile: u8,
+    pub message: String,
+    pub at: SystemTime,
+}
+
+struct PanicLog {
+    records: VecDeque<PanicRecord>,
+    capacity: usize,
+}
+
+impl PanicLog {
+    fn push(&mut self, record: PanicRecord) {
+        if self.capacity == 0 {
+            return;
+        }
+        while self.records.len() >= self.capacity {
+            self.records.pop_front();
+        }
+        self.records.push_back(record);
+    }
+
+    fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.records.len() > capacity {
+            self.records.pop_front();
+        }
+    }
+}
+
+static PANIC_LOG: Mutex<PanicLog> = Mutex::new(PanicLog {
+    records: VecDeque::new(),
+    capacity: DEFAULT_PANIC_BUFFER_CAPACITY,
+});
+
+/// the most recent panics of tasks spawned through the dispatch path, oldest first.
+/// recorded whether or not a panic hook is set, up to `set_panic_buffer_capacity` of them.
+pub fn recent_panics() -> Vec<PanicRecord> {
+    PANIC_LOG
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .records
+        .iter()
+        .cloned()
+        .collect()
+}
+
+/// keep at most `capacity` panics for `recent_panics`, dropping the oldest ones beyond it.
+/// 0 turns the buffer off.
+pub fn set_panic_buffer_capacity(capacity: usize) {
+    PANIC_LOG
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .set_capacity(capacity);
+}
+
+pub(crate) fn report_panic(profile: u8, message: &str) {
+    PANIC_LOG
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .push(PanicRecord {
+            profile,
+            message: message.to_string(),
+            at: SystemTime::now(),
+        });
+    let hook = PANIC_HOOK
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone();
+    if let Some(hook) = hook {
+        hook(profile, message);
+    }
+}
+
+/// lifecycle callbacks for the tasks dispatched on a profile, installed with `set_observer`.
+/// all methods default to doing nothing; they run on the awaiting task, so keep them cheap.
+pub trait TaskObserver: Send + Sync {
+    /// the task was spawned
+    fn on_spawn(&self, _profile: u8) {}
+    /// the task ended without being cut short: it sent its result or dropped the sender
+    fn on_complete(&self, _profile: u8) {}
+    /// no result within `Context.timeout`, the task gets aborted
+    fn on_timeout(&self, _profile: u8) {}
+    /// the task panicked before sending a result
+    fn on_panic(&self, _profile: u8, _message: &str) {}
+    /// the task was cancelled, e.g. by `trigger_shutdown` or the event of `go_until!`
+    fn on_cancel(&self, _profile: u8) {}
+    /// `timeout` was at least `UNBOUNDED_TIMEOUT`, so the call waits without one
+    fn on_timeout_ignored(&self, _profile: u8, _timeout: Duration) {}
+}
+
+static OBSERVERS: [RwLock<Option<Arc<dyn TaskObserver>>>; 256] = [const { RwLock::new(None) }; 256];
+
+/// install `observer` for every task dispatched on `profile`, replacing the previous one
+pub fn set_observer(profile: u8, observer: Arc<dyn TaskObserver>) {
+    *OBSERVERS[profile as usize]
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = Some(observer);
+}
+
+/// remove the observer of `profile`
+pub fn clear_observer(profile: u8) {
+    *OBSERVERS[profile as usize]
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = None;
+}
+
+pub(crate) fn observer(profile: u8) -> Option<Arc<dyn TaskObserver>> {
+    OBSERVERS[profile as usize]
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// tell `observer` how a task on `profile` ended
+pub(crate) fn observe_outcome<T>(observer: &dyn TaskObserver, profile: u8, r: &Result<T, GoError>) {
+    match r {
+        Ok(_) | Err(GoError::SenderDropped) => observer.on_complete(profile),
+        Err(GoError::Timeout { .. }) => observer.on_timeout(profile),
+        Err(GoError::Panicked(message)) => observer.on_panic(profile, message),
+        Err(GoError::Cancelled | GoError::Interrupted | GoError::RuntimeShutdown) => {
+            observer.on_cancel(profile)
+        }
+        Err(
+            GoError::Overloaded
+            | GoError::Draining
+            | GoError::RateLimited
+            | GoError::NoRuntime
+            | GoError::ReservedProfile,
+        ) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::go;
+    use crate::prelude::*;
+
+    #[tokio::test]
+    async fn panics_are_kept_in_order() {
+        let before = SystemTime::now();
+        for i in 0..3 {
+            let r = go!(
+                |_tx: Sender<()>| async move {
+                    panic!("boom {}", i);
+                },
+                Context {
+                    profile: 36,
+                    ..Default::default()
+                }
+            )
+            .await;
+            assert!(matches!(r, Err(GoError::Panicked(_))));
+        }
+        let seen: Vec<_> = recent_panics()
+            .into_iter()
+            .filter(|p| p.profile == 36)
+            .collect();
+        let messages: Vec<_> = seen.iter().map(|p| p.message.as_str()).collect();
+        assert_eq!(messages, vec!["boom 0", "boom 1", "boom 2"]);
+        assert!(seen.iter().all(|p| p.at >= before));
+    }
+
+    #[test]
+    fn panic_log_drops_oldest() {
+        let mut log = PanicLog {
+            records: VecDeque::new(),
+            capacity: 2,
+        };
+        for i in 0..3 {
+            log.push(PanicRecord {
+                profile: i,
+                message: String::new(),
+                at: SystemTime::now(),
+            });
+        }
+        let profiles: Vec<_> = log.records.iter().map(|p| p.profile).collect();
+        assert_eq!(profiles, vec![1, 2]);
+        log.set_capacity(1);
+        assert_eq!(log.records.len(), 1);
+        assert_eq!(log.records[0].profile, 2);
+    }
+}
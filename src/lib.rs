@@ -1,32 +1,210 @@
 pub mod prelude {
+    #[cfg(feature = "core_affinity")]
+    pub use crate::affinity::*;
+    pub use crate::autoscale::*;
+    pub use crate::batch::*;
+    pub use crate::cancel::{on_cancel, CancelHandle};
+    pub use crate::chan::*;
+    pub use crate::clock::*;
+    pub use crate::config::*;
+    pub use crate::dispatch::*;
+    pub use crate::error::GoError;
+    pub use crate::ext::*;
+    pub use crate::hook::*;
+    pub use crate::local::go_local;
+    pub use crate::pending::*;
+    pub use crate::profile::{init_runtime_named, named_profile, Profile};
+    pub use crate::ratelimit::*;
+    use crate::registry::SlotsWriteGuard;
+    pub use crate::registry::{set_default_worker_threads, Registry, RuntimeConfig, RuntimeFlavor};
+    pub use crate::scoped::*;
+    #[cfg(feature = "tower")]
+    pub use crate::service::GoService;
+    pub use crate::shutdown::*;
+    pub use crate::stats::*;
+    pub use crate::stream::*;
+    use std::sync::RwLockReadGuard;
     pub use std::sync::{Arc, RwLock};
+    pub use tokio::runtime::Handle;
     pub use tokio::runtime::Runtime;
+    pub use tokio::sync::mpsc;
     pub use tokio::sync::oneshot::{channel, Sender};
     pub use tokio::time::{interval, sleep, Duration};
-    const RUNTIME_INIT: Option<Runtime> = None;
-    lazy_static! {
-        pub static ref RUNTIMES: Arc<RwLock<[Option<Runtime>; 256]>> =
-            Arc::new(RwLock::new([RUNTIME_INIT; 256]));
-    }
-    #[derive(Debug)]
+    #[cfg(feature = "derive")]
+    pub use tokio_go_derive::Profile;
+    /// the runtimes `go!` and friends run on, one slot per profile
+    pub static RUNTIMES: Registry<256> = Registry::new();
+    #[derive(Debug, Clone, Default)]
     pub struct Context {
         pub profile: u8,
         pub timeout: Duration,
+        /// re-spawn the closure when an attempt times out, see `RetryPolicy`
+        pub auto_retry: Option<RetryPolicy>,
+        /// channel capacity for `go_stream!`, 0 means `DEFAULT_STREAM_CAPACITY`
+        pub stream_capacity: usize,
+        /// end a `go_stream!` when no item arrives within this long, `timeout` if `None`
+        pub item_timeout: Option<Duration>,
+        /// bound the whole of a `go_all!` batch, on top of `timeout`
+        pub batch_timeout: Option<Duration>,
+        /// make `go_map` and `go_all!` apply `timeout` to every item on its own rather than
+        /// the whole batch
+        pub per_task_timeout: bool,
+        /// run on this profile instead when `profile` is draining or overloaded
+        pub fallback_profile: Option<u8>,
+        /// fail with `GoError::RateLimited` instead of waiting when `set_rate_limit` is exceeded
+        pub fail_fast_on_rate_limit: bool,
+        /// a name for the spawned task in `dump_inflight`
+        pub label: Option<String>,
+        /// time source for `timeout`, e.g. a `VirtualClock` in simulations. tokio's timer if `None`
+        pub clock: Option<Arc<dyn Clock>>,
+        /// cancel the task once `trigger_shutdown` is called
+        pub observe_global_shutdown: bool,
+        /// `profile` comes from a name (`Context::named`), as the slots of named profiles
+        /// can't be used by number
+        pub named: bool,
     }
 
-    pub fn init_runtime(profile: u8) {
-        {
-            let r = RUNTIMES.read().unwrap();
-            if r[profile as usize].is_some() {
-                return;
+    /// how often a timed out `go!` call gets re-spawned.
+    /// retrying needs to call the closure again, so it must be `Fn`, not just `FnOnce`
+    #[derive(Debug, Clone, Copy)]
+    pub struct RetryPolicy {
+        /// total attempts including the first one
+        pub max_attempts: u32,
+        /// pause between a timed out attempt and the next one
+        pub backoff: Duration,
+    }
+
+    impl Default for RetryPolicy {
+        fn default() -> Self {
+            RetryPolicy {
+                max_attempts: 1,
+                backoff: Duration::ZERO,
+            }
+        }
+    }
+
+    impl Context {
+        /// a `Context` for `profile` with default settings, to chain the other builders on:
+        /// `Context::profile(1).timeout(d).retries(3).backoff(pause)`
+        pub fn profile(profile: u8) -> Context {
+            Context {
+                profile,
+                ..Default::default()
             }
         }
-        let mut w = RUNTIMES.write().unwrap();
-        if w[profile as usize].is_none() {
-            w[profile as usize] = Some(Runtime::new().unwrap());
+
+        /// this context with `timeout` set
+        pub fn timeout(mut self, timeout: Duration) -> Context {
+            self.timeout = timeout;
+            self
+        }
+
+        /// re-spawn the closure up to `retries` more times after an attempt times out,
+        /// keeping the policy's backoff. only timeouts are retried, see `RetryPolicy`
+        pub fn retries(mut self, retries: u32) -> Context {
+            let policy = self.auto_retry.unwrap_or_default();
+            self.auto_retry = Some(RetryPolicy {
+                max_attempts: retries.saturating_add(1),
+                ..policy
+            });
+            self
         }
+
+        /// pause for `backoff` between a timed out attempt and the next one
+        pub fn backoff(mut self, backoff: Duration) -> Context {
+            let policy = self.auto_retry.unwrap_or_default();
+            self.auto_retry = Some(RetryPolicy { backoff, ..policy });
+            self
+        }
+    }
+
+    /// create the runtime of `profile` unless it exists. panics if `profile` is the slot of a
+    /// named profile, see `init_runtime_named`
+    pub fn init_runtime(profile: u8) {
+        crate::profile::assert_numbered(profile);
+        RUNTIMES.init_runtime(profile)
+    }
+
+    /// initialize `profile` with its own runtime settings, e.g. a few named workers for IO
+    /// and a single thread for latency sensitive work. a no-op if the profile is already
+    /// initialized, like `init_runtime`; profiles never configured get the default settings
+    /// when `go!` first uses them. panics like `init_runtime` on the slot of a named profile.
+    /// # Example:
+    /// ```
+    /// use tokio_go::prelude::*;
+    /// init_runtime_with(3, RuntimeConfig {
+    ///     worker_threads: Some(2),
+    ///     thread_name_prefix: Some("io".to_string()),
+    ///     ..Default::default()
+    /// });
+    /// init_runtime_with(4, RuntimeConfig {
+    ///     flavor: RuntimeFlavor::SingleWorker,
+    ///     ..Default::default()
+    /// });
+    /// ```
+    pub fn init_runtime_with(profile: u8, cfg: RuntimeConfig) {
+        crate::profile::assert_numbered(profile);
+        RUNTIMES.init_runtime_with(profile, cfg)
+    }
+
+    /// shut down the runtime of `profile`, giving its blocking threads up to `grace` to
+    /// finish, and empty its slot so the next `init_runtime` or `go!` creates a fresh one.
+    /// tasks still running on it are dropped and their `go!` calls fail with
+    /// `GoError::RuntimeShutdown`. a no-op for a profile that was never initialized.
+    ///
+    /// blocks the calling thread for up to `grace`; called from a task on `profile` itself,
+    /// it waits the full `grace` for its own worker.
+    pub fn shutdown_runtime(profile: u8, grace: Duration) {
+        RUNTIMES.shutdown_runtime(profile, grace)
+    }
+
+    /// `shutdown_runtime` for every profile, shut down in parallel within the same `grace`
+    pub fn shutdown_all(grace: Duration) {
+        RUNTIMES.shutdown_all(grace)
+    }
+
+    /// initialize the runtime of `profile` if needed and return a cloned handle to it,
+    /// so that callers don't keep `RUNTIMES` locked while spawning or awaiting
+    pub fn runtime_handle(profile: u8) -> Handle {
+        RUNTIMES.runtime_handle(profile)
+    }
+
+    /// read `RUNTIMES`, tolerating lock poisoning
+    pub(crate) fn runtimes_read() -> RwLockReadGuard<'static, [Option<Runtime>; 256]> {
+        RUNTIMES.read_slots()
+    }
+
+    /// write `RUNTIMES`, tolerating lock poisoning and refreshing the handle snapshot
+    pub(crate) fn runtimes_write() -> SlotsWriteGuard<'static, 256> {
+        RUNTIMES.write_slots()
     }
 }
+
+#[cfg(feature = "core_affinity")]
+mod affinity;
+mod autoscale;
+mod batch;
+mod cancel;
+mod chan;
+mod clock;
+mod config;
+mod dispatch;
+mod error;
+mod ext;
+mod hook;
+mod local;
+mod pending;
+mod profile;
+mod ratelimit;
+mod registry;
+mod scoped;
+#[cfg(feature = "tower")]
+mod service;
+mod shutdown;
+mod stats;
+mod stream;
+mod trace;
+
 /// support running a async closure in default or specified tokio runtime
 /// # Example:
 /// using default runtime, without timeout
@@ -56,69 +234,718 @@ pub mod prelude {
 /// Context{
 ///     profile: 1,
 ///     timeout: Duration::from_secs(1),
+///     ..Default::default()
 /// }
 /// ).await;
-/// assert_eq!(r.is_ok(),false);
+/// assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+/// }
+/// ```
+///
+/// a task that times out is aborted. with `Context.auto_retry` set it is re-spawned up to
+/// `max_attempts` times, so the closure passed together with a `Context` has to be `Fn`;
+/// `GoError::Timeout` then says how many attempts were made. other errors aren't retried.
+/// instead of a `Context` any `Profile` (e.g. an enum of workloads) can be passed to run
+/// on that profile with default settings.
+/// the `go` and `go_with` functions run the same path for any `FnOnce`, e.g. a named async
+/// fn or a boxed closure kept for later.
+///
+/// an async block can be passed instead of a closure: its output is the result, no `Sender`
+/// plumbing needed. it runs once, so `Context.auto_retry` doesn't apply to it.
+///
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go;
+/// #[tokio::main]
+/// async fn main(){
+/// let r = go!(async move { 21 * 2 }, Context::profile(1).timeout(Duration::from_secs(1))).await;
+/// assert_eq!(r, Ok(42));
+/// }
+/// ```
+///
+/// with the `tracing` feature the task runs in a `go_task{profile, timeout_ms}` span, a child
+/// of the caller's current span, and an event with its `elapsed_ms` and `outcome` is
+/// recorded once the call returns (`warn` for a timeout, `debug` otherwise). every other
+/// way of spawning here does the same; for tasks nothing waits on (`go!(sender, ..)`,
+/// streams, `go_spawn!`) the event is recorded when the task itself ends.
+///
+/// passing an existing sender first spawns the closure with it and returns the task's
+/// `JoinHandle` (`Err` without spawning when the profile refuses it), the caller keeps the
+/// matching receiver. a oneshot sender is consumed by
+/// sending, so each call needs a fresh pair; reusing one that was used to send is invalid.
+///
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go;
+/// #[tokio::main]
+/// async fn main(){
+/// let (tx, rx) = channel::<i32>();
+/// go!(tx, |tx: Sender<i32>|async move{
+///     let _ = tx.send(1);
+/// });
+/// assert_eq!(rx.await.unwrap(), 1);
 /// }
 /// ```
-
 #[macro_export]
 macro_rules! go {
     (|$x:ident : Sender<$t:ty>|$y:expr) => {
-        async {
-            let (sender, receiver) = channel::<$t>();
-            init_runtime(0);
-            let rts = RUNTIMES.read().unwrap();
-            let runtime = rts[0].as_ref().unwrap();
-            runtime.spawn((|$x: Sender<$t>| $y)(sender));
-            match receiver.await {
-                Ok(v) => Ok(v),
-                Err(_) => Err("unknown error"),
-            }
-        }
+        $crate::prelude::GoFuture::new($crate::prelude::dispatch_once(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            $crate::prelude::Context::default(),
+        ))
     };
     (|$x:ident : Sender<$t:ty>|$y:expr,$c:expr) => {
-        async {
-            let (sender, mut receiver) = channel::<$t>();
-            init_runtime($c.profile);
-            let rts = RUNTIMES.read().unwrap();
-            let runtime = rts[$c.profile as usize].as_ref().unwrap();
-            runtime.spawn((|$x: Sender<$t>| $y)(sender));
-            match $c.timeout {
-                Duration::ZERO => match receiver.await {
-                    Ok(v) => Ok(v),
-                    Err(_) => Err("unknown error"),
-                },
-                _ => {
-                    let mut interval = interval($c.timeout);
-                    tokio::select! {
-                        _=interval.tick()=>Err("timeout"),
-                        msg=&mut receiver =>match msg{
-                            Ok(v)=>Ok(v),
-                            Err(_)=>Err("unknown error")
-                        }
-
-                    }
-                }
-            }
-        }
+        $crate::prelude::GoFuture::new($crate::prelude::dispatch(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        ))
+    };
+    (async move $b:block) => {
+        $crate::prelude::GoFuture::new($crate::prelude::dispatch_value(
+            async move $b,
+            $crate::prelude::Context::default(),
+        ))
+    };
+    (async move $b:block, $c:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::dispatch_value(
+            async move $b,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        ))
+    };
+    (async $b:block) => {
+        $crate::prelude::GoFuture::new($crate::prelude::dispatch_value(
+            async $b,
+            $crate::prelude::Context::default(),
+        ))
+    };
+    (async $b:block, $c:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::dispatch_value(
+            async $b,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        ))
+    };
+    ($s:expr, |$x:ident : Sender<$t:ty>|$y:expr) => {
+        $crate::prelude::go_with_sender(
+            $s,
+            |$x: $crate::prelude::Sender<$t>| $y,
+            $crate::prelude::Context::default(),
+        )
+    };
+    ($s:expr, |$x:ident : Sender<$t:ty>|$y:expr,$c:expr) => {
+        $crate::prelude::go_with_sender(
+            $s,
+            |$x: $crate::prelude::Sender<$t>| $y,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        )
+    };
+}
+
+/// like `go!`, but runs on whichever of the given profiles has the fewest tasks in flight
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_balanced;
+/// #[tokio::main]
+/// async fn main(){
+/// let r = go_balanced!(&[1, 2], |tx: Sender<i32>|async move{
+///     let _ = tx.send(1);
+/// }).await;
+/// assert_eq!(r, Ok(1));
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_balanced {
+    ($p:expr, |$x:ident : Sender<$t:ty>|$y:expr) => {
+        $crate::prelude::go_balanced($p, |$x: $crate::prelude::Sender<$t>| $y)
+    };
+}
+
+/// like `go!`, but the closure is run again when it panics, up to `max_restarts` times.
+/// each panic is reported to the hook set with `set_panic_hook`. the closure has to be `Fn`.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::supervise;
+/// use std::sync::atomic::{AtomicU32, Ordering};
+/// #[tokio::main]
+/// async fn main(){
+/// let calls = AtomicU32::new(0);
+/// let r = supervise!(|tx: Sender<u32>|{
+///     let n = calls.fetch_add(1, Ordering::SeqCst);
+///     async move{
+///         if n == 0 {
+///             panic!("first attempt fails");
+///         }
+///         let _ = tx.send(n);
+///     }
+/// }, 3).await;
+/// assert_eq!(r, Ok(1));
+/// }
+/// ```
+#[macro_export]
+macro_rules! supervise {
+    (|$x:ident : Sender<$t:ty>|$y:expr, $n:expr) => {
+        $crate::prelude::supervise(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            $n,
+            $crate::prelude::Context::default(),
+        )
+    };
+    (|$x:ident : Sender<$t:ty>|$y:expr, $n:expr, $c:expr) => {
+        $crate::prelude::supervise(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            $n,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        )
+    };
+}
+
+/// like `go!` with a `Context`, but returns a `Stream` of `AttemptResult`s, one for every
+/// attempt `Context.auto_retry` makes, ending with the first success or the last attempt.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_retry_stream;
+/// use futures::StreamExt;
+/// #[tokio::main]
+/// async fn main(){
+/// let attempts: Vec<_> = go_retry_stream!(|tx: Sender<i32>|async move{
+///     sleep(Duration::from_secs(2)).await;
+///     let _ = tx.send(1);
+/// },
+/// Context{
+///     timeout: Duration::from_millis(50),
+///     auto_retry: Some(RetryPolicy{ max_attempts: 2, backoff: Duration::ZERO }),
+///     ..Default::default()
+/// }
+/// ).collect().await;
+/// for a in &attempts {
+///     println!("attempt {} took {:?}: {:?}", a.attempt, a.elapsed, a.result);
+/// }
+/// assert_eq!(attempts.len(), 2);
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_retry_stream {
+    (|$x:ident : Sender<$t:ty>|$y:expr,$c:expr) => {
+        $crate::prelude::retry_attempts(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        )
+    };
+}
+
+/// like `go!`, but waiting for the result also checks for it every `poll_interval`, as a
+/// fallback for when a wakeup might get lost, e.g. when bridging to non-tokio wakers over
+/// FFI. a niche robustness feature: with tokio alone the plain `go!` never misses a result.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_poll;
+/// #[tokio::main]
+/// async fn main(){
+/// let r = go_poll!(|tx: Sender<i32>|async move{
+///     let _ = tx.send(1);
+/// }, Duration::from_millis(100)).await;
+/// assert_eq!(r, Ok(1));
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_poll {
+    (|$x:ident : Sender<$t:ty>|$y:expr, $i:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::go_poll(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            $i,
+            $crate::prelude::Context::default(),
+        ))
+    };
+    (|$x:ident : Sender<$t:ty>|$y:expr, $i:expr, $c:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::go_poll(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            $i,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        ))
+    };
+}
+
+/// like `go!`, but the task is aborted and the call fails with `GoError::Interrupted` as soon
+/// as the given event future completes, e.g. for "run until the connection drops".
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_until;
+/// #[tokio::main]
+/// async fn main(){
+/// let r = go_until!(|tx: Sender<i32>|async move{
+///     sleep(Duration::from_secs(2)).await;
+///     let _ = tx.send(1);
+/// }, sleep(Duration::from_millis(50))).await;
+/// assert_eq!(r, Err(GoError::Interrupted));
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_until {
+    (|$x:ident : Sender<$t:ty>|$y:expr, $e:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::go_until(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            $e,
+            $crate::prelude::Context::default(),
+        ))
+    };
+    (|$x:ident : Sender<$t:ty>|$y:expr, $e:expr, $c:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::go_until(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            $e,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        ))
+    };
+}
+
+/// like `go!`, but returns the call together with a `CancelHandle`. `cancel()` aborts the
+/// task and the call returns `GoError::Cancelled` right away instead of waiting for the
+/// task or its timeout; a result that was already sent is still returned. dropping the
+/// handle doesn't cancel anything.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_cancelable;
+/// #[tokio::main]
+/// async fn main(){
+/// let (call, cancel) = go_cancelable!(|tx: Sender<i32>|async move{
+///     sleep(Duration::from_secs(10)).await;
+///     let _ = tx.send(1);
+/// });
+/// cancel.cancel();
+/// assert_eq!(call.await, Err(GoError::Cancelled));
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_cancelable {
+    (|$x:ident : Sender<$t:ty>|$y:expr) => {
+        $crate::go_cancelable!(|$x: Sender<$t>| $y, $crate::prelude::Context::default())
+    };
+    (|$x:ident : Sender<$t:ty>|$y:expr, $c:expr) => {{
+        let (call, cancel) = $crate::prelude::go_cancelable(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        );
+        ($crate::prelude::GoFuture::new(call), cancel)
+    }};
+}
+
+/// like `go!`, but a timeout doesn't abort the task: the result is `(Err(Timeout), Some(late))`
+/// where `late` receives the value if the task still sends it. otherwise `late` is `None`.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_hedged;
+/// #[tokio::main]
+/// async fn main(){
+/// let (r, late) = go_hedged!(|tx: Sender<i32>|async move{
+///     sleep(Duration::from_millis(200)).await;
+///     let _ = tx.send(1);
+/// },
+/// Context{
+///     timeout: Duration::from_millis(50),
+///     ..Default::default()
+/// }
+/// ).await;
+/// assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+/// assert_eq!(late.unwrap().await.unwrap(), 1);
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_hedged {
+    (|$x:ident : Sender<$t:ty>|$y:expr,$c:expr) => {
+        $crate::prelude::go_hedged(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        )
+    };
+}
+
+/// spawn a closure on a profile (or any `Context`) immediately and return a `Pending`
+/// handle for its result
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_spawn;
+/// #[tokio::main]
+/// async fn main(){
+/// let pending = go_spawn!(1, |tx: Sender<i32>|async move{
+///     let _ = tx.send(1);
+/// });
+/// assert_eq!(pending.await, Ok(1));
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_spawn {
+    ($p:expr, |$x:ident : Sender<$t:ty>|$y:expr) => {
+        $crate::prelude::go_spawn(
+            ::std::convert::Into::<$crate::prelude::Context>::into($p),
+            |$x: $crate::prelude::Sender<$t>| $y,
+        )
+    };
+}
+
+/// non-blocking probe over `Pending` handles: the index and result of the first one that
+/// already finished, or `None`
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::{go_first_ready, go_spawn};
+/// #[tokio::main]
+/// async fn main(){
+/// let mut a = go_spawn!(1, |tx: Sender<i32>|async move{
+///     sleep(Duration::from_secs(1)).await;
+///     let _ = tx.send(1);
+/// });
+/// assert_eq!(go_first_ready!(a), None);
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_first_ready {
+    ($($p:expr),+ $(,)?) => {
+        $crate::prelude::first_ready([$(&mut $p),+])
+    };
+}
+
+/// like `go!`, but runs on the profile a key hashes to, so equal keys always share a runtime.
+/// the candidate profiles are set with `set_sticky_profiles`.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_sticky;
+/// #[tokio::main]
+/// async fn main(){
+/// set_sticky_profiles(&[1, 2, 3]);
+/// let r = go_sticky!("user-42", |tx: Sender<i32>|async move{
+///     let _ = tx.send(1);
+/// }).await;
+/// assert_eq!(r, Ok(1));
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_sticky {
+    ($k:expr, |$x:ident : Sender<$t:ty>|$y:expr) => {
+        $crate::prelude::go_sticky(&$k, |$x: $crate::prelude::Sender<$t>| $y)
+    };
+}
+
+/// spawn a closure on `profile` in the background and log its error with `tracing` (the
+/// `tracing` feature) instead of returning it. the caller is not blocked, the returned
+/// `JoinHandle` may be dropped.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_log_err;
+/// #[tokio::main]
+/// async fn main(){
+/// go_log_err!(1, |tx: Sender<i32>|async move{
+///     let _ = tx.send(1);
+/// });
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_log_err {
+    ($p:expr, |$x:ident : Sender<$t:ty>|$y:expr) => {
+        $crate::prelude::go_log_err($p, |$x: $crate::prelude::Sender<$t>| $y)
+    };
+}
+
+/// race futures returning `Result<T, GoError>` (typically `go!` calls) and stream
+/// `(index, result, elapsed)` in completion order. the futures may be of different types.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::{go, go_race_all};
+/// use futures::StreamExt;
+/// #[tokio::main]
+/// async fn main(){
+/// let a = go!(|tx: Sender<i32>|async move{
+///     sleep(Duration::from_millis(100)).await;
+///     let _ = tx.send(1);
+/// });
+/// let b = go!(|tx: Sender<i32>|async move{
+///     let _ = tx.send(2);
+/// });
+/// let (first, r, _elapsed) = go_race_all!(a, b).next().await.unwrap();
+/// assert_eq!((first, r), (1, Ok(2)));
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_race_all {
+    ($($task:expr),+ $(,)?) => {
+        $crate::prelude::race_all(::std::vec![$(::std::boxed::Box::pin($task)
+            as ::std::pin::Pin<::std::boxed::Box<dyn ::std::future::Future<Output = _> + Send>>),+])
+    };
+}
+
+/// like `go!` for a list of closures, which all run on the same profile. resolves to a
+/// `Vec` of their results in input order; `Context.timeout` is one deadline for the whole
+/// batch, after which the unfinished tasks are aborted. see `go_all`.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_all;
+/// #[tokio::main]
+/// async fn main(){
+/// let results = go_all!([
+///     |tx: Sender<i32>|async move{
+///         let _ = tx.send(1);
+///     },
+///     |tx: Sender<i32>|async move{
+///         sleep(Duration::from_secs(2)).await;
+///         let _ = tx.send(2);
+///     },
+/// ],
+/// Context{
+///     profile: 1,
+///     timeout: Duration::from_millis(100),
+///     ..Default::default()
+/// }
+/// ).await;
+/// assert_eq!(results, vec![Ok(1), Err(GoError::Timeout { attempts: 1 })]);
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_all {
+    ([$(|$x:ident : Sender<$t:ty>|$y:expr),+ $(,)?]) => {
+        $crate::go_all!([$(|$x: Sender<$t>| $y),+], $crate::prelude::Context::default())
+    };
+    ([$(|$x:ident : Sender<$t:ty>|$y:expr),+ $(,)?], $c:expr) => {
+        $crate::prelude::go_all(
+            ::std::vec![$(::std::boxed::Box::new(|$x: $crate::prelude::Sender<$t>| {
+                ::std::boxed::Box::pin($y)
+                    as ::std::pin::Pin<::std::boxed::Box<dyn ::std::future::Future<Output = ()> + Send>>
+            }) as $crate::prelude::BoxedTask<_>),+],
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        )
+    };
+}
+
+/// like `go!`, but spawns onto the runtime the caller is running on rather than a runtime of
+/// `RUNTIMES`, e.g. in library code that shouldn't start threads of its own. the runtime is
+/// the one current where `go_here!` is called; outside of one, the call fails with
+/// `GoError::NoRuntime`. timeouts and retries work as with `go!`, while the profile only
+/// selects counters, limits and hooks (its runtime is never created).
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_here;
+/// #[tokio::main]
+/// async fn main(){
+/// let r = go_here!(|tx: Sender<i32>|async move{
+///     let _ = tx.send(1);
+/// }).await;
+/// assert_eq!(r, Ok(1));
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_here {
+    (|$x:ident : Sender<$t:ty>|$y:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::dispatch_here(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            $crate::prelude::Context::default(),
+        ))
+    };
+    (|$x:ident : Sender<$t:ty>|$y:expr, $c:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::dispatch_here(
+            |$x: $crate::prelude::Sender<$t>| $y,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        ))
+    };
+}
+
+/// like `go!`, but the future doesn't have to be `Send`: it runs on a thread dedicated to
+/// the profile, started on first use, where it may hold `Rc`s or `RefCell`s across awaits.
+/// the closure, which moves what it captures, and the result still have to be `Send`.
+/// calls on one profile start in the order they are dispatched, see `go_local`.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_local;
+/// use std::rc::Rc;
+/// #[tokio::main]
+/// async fn main(){
+/// let r = go_local!(|tx: Sender<usize>|async move{
+///     let shared = Rc::new(vec![1, 2, 3]);
+///     sleep(Duration::from_millis(10)).await;
+///     let _ = tx.send(shared.len());
+/// }).await;
+/// assert_eq!(r, Ok(3));
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_local {
+    (|$x:ident : Sender<$t:ty>|$y:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::go_local(
+            move |$x: $crate::prelude::Sender<$t>| $y,
+            $crate::prelude::Context::default(),
+        ))
+    };
+    (|$x:ident : Sender<$t:ty>|$y:expr, $c:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::go_local(
+            move |$x: $crate::prelude::Sender<$t>| $y,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        ))
+    };
+}
+
+/// like `go!` for a plain blocking closure returning its value, e.g. compression or a call
+/// into a blocking C library. it runs on the profile's blocking pool, so it doesn't hold up
+/// the profile's async workers. on timeout the call returns `GoError::Timeout` but the
+/// closure keeps running to completion in the background, see `go_blocking`.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_blocking;
+/// #[tokio::main]
+/// async fn main(){
+/// let r = go_blocking!(|| {
+///     std::thread::sleep(Duration::from_millis(10));
+///     1
+/// }).await;
+/// assert_eq!(r, Ok(1));
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_blocking {
+    ($f:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::go_blocking(
+            $f,
+            $crate::prelude::Context::default(),
+        ))
+    };
+    ($f:expr, $c:expr) => {
+        $crate::prelude::GoFuture::new($crate::prelude::go_blocking(
+            $f,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        ))
+    };
+}
+
+/// register a cleanup future from inside a `go!` task that is spawned only if the task is
+/// cancelled or aborted on timeout, e.g. to release a remote lease. returns whether it was
+/// registered, which fails outside of `go!` tasks.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::{go, on_cancel};
+/// #[tokio::main]
+/// async fn main(){
+/// let r = go!(|tx: Sender<i32>|async move{
+///     on_cancel!(async { println!("releasing lease") });
+///     sleep(Duration::from_secs(2)).await;
+///     let _ = tx.send(1);
+/// },
+/// Context{
+///     profile: 1,
+///     timeout: Duration::from_millis(100),
+///     ..Default::default()
+/// }
+/// ).await;
+/// assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+/// }
+/// ```
+#[macro_export]
+macro_rules! on_cancel {
+    ($cleanup:expr) => {
+        $crate::prelude::on_cancel($cleanup)
+    };
+}
+
+/// like `go!`, but the closure gets a `mpsc::Sender` and may send any number of items,
+/// which are returned as a `Stream`. the task is spawned immediately. a `timeout` in the
+/// `Context` applies to each item rather than the whole stream, see `go_stream`.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_stream;
+/// use futures::StreamExt;
+/// #[tokio::main]
+/// async fn main(){
+/// let items: Vec<i32> = go_stream!(|tx: mpsc::Sender<i32>|async move{
+///     for i in 0..3 {
+///         let _ = tx.send(i).await;
+///     }
+/// },
+/// Context{
+///     profile: 1,
+///     stream_capacity: 1,
+///     ..Default::default()
+/// }
+/// ).collect().await;
+/// assert_eq!(items, vec![0, 1, 2]);
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_stream {
+    (|$x:ident : mpsc::Sender<$t:ty>|$y:expr) => {
+        $crate::prelude::go_stream(
+            |$x: $crate::prelude::mpsc::Sender<$t>| $y,
+            $crate::prelude::Context::default(),
+        )
+    };
+    (|$x:ident : mpsc::Sender<$t:ty>|$y:expr,$c:expr) => {
+        $crate::prelude::go_stream(
+            |$x: $crate::prelude::mpsc::Sender<$t>| $y,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        )
+    };
+}
+
+/// like `go_stream!`, but yields `(item, gap)` where `gap` is the time since the previous
+/// item was sent, or since the start for the first one.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go_stream_timed;
+/// use futures::StreamExt;
+/// #[tokio::main]
+/// async fn main(){
+/// let items: Vec<(i32, Duration)> = go_stream_timed!(|tx: mpsc::Sender<i32>|async move{
+///     for i in 0..3 {
+///         let _ = tx.send(i).await;
+///     }
+/// }).collect().await;
+/// assert_eq!(items.len(), 3);
+/// }
+/// ```
+#[macro_export]
+macro_rules! go_stream_timed {
+    (|$x:ident : mpsc::Sender<$t:ty>|$y:expr) => {
+        $crate::prelude::go_stream_timed(
+            |$x: $crate::prelude::mpsc::Sender<$t>| $y,
+            $crate::prelude::Context::default(),
+        )
+    };
+    (|$x:ident : mpsc::Sender<$t:ty>|$y:expr,$c:expr) => {
+        $crate::prelude::go_stream_timed(
+            |$x: $crate::prelude::mpsc::Sender<$t>| $y,
+            ::std::convert::Into::<$crate::prelude::Context>::into($c),
+        )
     };
 }
 
 #[macro_use]
 extern crate lazy_static;
 
+// lets `#[derive(Profile)]` refer to `::tokio_go` from within this crate too
+extern crate self as tokio_go;
+
+#[cfg(all(test, feature = "tracing"))]
+mod test_util;
+
 #[cfg(test)]
 mod tests {
     use super::prelude::*;
-    use super::*;
     use std::thread;
 
     #[tokio::test]
     async fn it_works() {
         let r1 = go!(|sender: Sender<i32>| async move {
             println!("Thread id: {:?}", thread::current().id());
-            if let Err(_) = sender.send(2) {
+            if sender.send(2).is_err() {
                 println!("the receiver dropped");
             }
         })
@@ -128,13 +955,14 @@ mod tests {
         let r2 = go!(
             |sender: Sender<String>| async move {
                 println!("Thread id: {:?}", thread::current().id());
-                if let Err(_) = sender.send("whocares".to_string()) {
+                if sender.send("whocares".to_string()).is_err() {
                     println!("the receiver dropped");
                 }
             },
             Context {
                 profile: 1,
-                timeout: Duration::from_secs(1)
+                timeout: Duration::from_secs(1),
+                ..Default::default()
             }
         )
         .await
@@ -143,13 +971,14 @@ mod tests {
         let r3 = go!(
             |sender: Sender<String>| async move {
                 println!("Thread id: {:?}", thread::current().id());
-                if let Err(_) = sender.send("whocares".to_string()) {
+                if sender.send("whocares".to_string()).is_err() {
                     println!("the receiver dropped");
                 }
             },
             Context {
                 profile: 1,
-                timeout: Duration::ZERO
+                timeout: Duration::ZERO,
+                ..Default::default()
             }
         )
         .await
@@ -157,12 +986,119 @@ mod tests {
         assert_eq!(r3, "whocares");
         let r4 = go!(|sender: Sender<()>| async move {
             println!("Thread id: {:?}", thread::current().id());
-            if let Err(_) = sender.send(()) {
+            if sender.send(()).is_err() {
                 println!("the receiver dropped");
             }
         })
+        .await;
+        assert!(r4.is_ok());
+    }
+
+    #[tokio::test]
+    async fn timeout_fires() {
+        let r = go!(
+            |tx: Sender<()>| async move {
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(());
+            },
+            Context {
+                timeout: Duration::from_millis(20),
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        let e: Box<dyn std::error::Error> = Box::new(r.unwrap_err());
+        assert_eq!(e.to_string(), "timeout");
+    }
+
+    #[tokio::test]
+    async fn value_form_returns_the_output() {
+        assert_eq!(go!(async move { 1 + 1 }).await, Ok(2));
+        assert_eq!(go!(async {}).await, Ok(()));
+        let greeting = "hello".to_string();
+        let r = go!(
+            async move { format!("{} from {:?}", greeting, thread::current().name()) },
+            Context::profile(2).timeout(Duration::from_secs(1))
+        )
         .await
         .unwrap();
-        assert_eq!(r4, ());
+        assert!(r.starts_with("hello from"), "{}", r);
+        let r = go!(
+            async move { sleep(Duration::from_secs(5)).await },
+            Context::default().timeout(Duration::from_millis(20))
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        let r: Result<i32, GoError> = go!(async move { panic!("value form") }).await;
+        assert_eq!(r, Err(GoError::Panicked("value form".to_string())));
+    }
+
+    #[tokio::test]
+    async fn dropped_sender_is_an_error() {
+        let r = go!(|tx: Sender<i32>| async move { drop(tx) }).await;
+        assert_eq!(r, Err(GoError::SenderDropped));
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                if tx.is_closed() {
+                    return;
+                }
+                sleep(Duration::from_millis(10)).await;
+            },
+            Context {
+                timeout: Duration::from_secs(1),
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r, Err(GoError::SenderDropped));
+    }
+
+    #[tokio::test]
+    async fn panics_are_surfaced() {
+        let r = go!(|_tx: Sender<i32>| async move { panic!("right away") }).await;
+        assert_eq!(r, Err(GoError::Panicked("right away".to_string())));
+        let r = go!(
+            |_tx: Sender<i32>| async move {
+                sleep(Duration::from_millis(10)).await;
+                panic!("after {}ms", 10);
+            },
+            Context {
+                timeout: Duration::from_secs(1),
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Panicked("after 10ms".to_string())));
+        let r = go!(|tx: Sender<i32>| async move {
+            let _ = tx.send(1);
+            panic!("after sending");
+        })
+        .await;
+        assert_eq!(r, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn spawns_survive_poisoned_runtimes_lock() {
+        // a registry of its own: poisoning the global one would leak into other tests
+        static POISONED: Registry<4> = Registry::new();
+        let poisoner = thread::spawn(|| {
+            let _w = POISONED.write().unwrap();
+            panic!("poison the registry");
+        });
+        assert!(poisoner.join().is_err());
+        assert!(POISONED.is_poisoned());
+        let r = POISONED
+            .go(
+                |tx: Sender<i32>| async move {
+                    let _ = tx.send(1);
+                },
+                Context {
+                    profile: 3,
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert_eq!(r, Ok(1));
     }
 }
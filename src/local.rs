@@ -0,0 +1,161 @@
+use crate::cancel::cancel_scope;
+use crate::dispatch::SpawnedTask;
+use crate::prelude::*;
+use crate::profile::check_named;
+use crate::ratelimit::take_token;
+use crate::stats::{acquire_result_slot, track_labeled};
+use crate::trace::TaskTrace;
+use std::future::Future;
+use std::sync::OnceLock;
+use tokio::runtime::Builder;
+use tokio::sync::oneshot;
+use tokio::task::{JoinHandle, LocalSet};
+
+/// a `go_local!` closure, run on the local worker thread where it spawns its future
+type LocalJob = Box<dyn FnOnce() + Send>;
+
+static LOCAL_WORKERS: [OnceLock<mpsc::UnboundedSender<LocalJob>>; 256] =
+    [const { OnceLock::new() }; 256];
+
+/// the queue of the local worker of `profile`, starting the worker on first use: a thread of
+/// its own running a current thread runtime with a `LocalSet`, which lives as long as the
+/// process. nothing here is shared with the profile's runtime in `RUNTIMES`, apart from the
+/// counters and hooks keyed by profile number.
+fn local_worker(profile: u8) -> &'static mpsc::UnboundedSender<LocalJob> {
+    LOCAL_WORKERS[profile as usize].get_or_init(|| {
+        let (jobs, mut queue) = mpsc::unbounded_channel::<LocalJob>();
+        let runtime = Builder::new_current_thread().enable_all().build().unwrap();
+        std::thread::Builder::new()
+            .name(format!("tokio-go-local-{}", profile))
+            .spawn(move || {
+                LocalSet::new().block_on(&runtime, async move {
+                    while let Some(job) = queue.recv().await {
+                        job();
+                    }
+                })
+            })
+            .expect("failed to start the local worker thread");
+        jobs
+    })
+}
+
+/// run `f` on the local worker of `ctx.profile`, a dedicated thread per profile, so the
+/// future it builds doesn't have to be `Send` (it may hold an `Rc` across an await); the
+/// closure itself and the result still cross threads. honours `ctx` like `go!`, except
+/// that `ctx.auto_retry` and `ctx.fallback_profile` don't apply.
+///
+/// calls on one profile are started in the order they are dispatched (first polled): the
+/// worker spawns them in FIFO order and a task's first poll comes before any later one's.
+/// once they await, they interleave like any tasks on one thread.
+#[doc(hidden)]
+pub async fn go_local<T, F, Fut>(f: F, ctx: Context) -> Result<T, GoError>
+where
+    F: FnOnce(Sender<T>) -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + 'static,
+    T: Send + 'static,
+{
+    let profile = ctx.profile;
+    check_named(&ctx)?;
+    let slot = acquire_result_slot(profile)?;
+    take_token(profile, ctx.fail_fast_on_rate_limit).await?;
+    let (sender, receiver) = channel::<T>();
+    let (spawned, joined) = oneshot::channel::<(JoinHandle<()>, Handle)>();
+    let label = ctx.label.clone();
+    let trace = TaskTrace::new(profile, &ctx);
+    let task_trace = trace.clone();
+    let job: LocalJob = Box::new(move || {
+        // `f` is called inside the task, so a panic in it fails this call, not the worker
+        let join = tokio::task::spawn_local(async move {
+            let task = task_trace.instrument(cancel_scope(f(sender)));
+            track_labeled(profile, label, task).await
+        });
+        let _ = spawned.send((join, Handle::current()));
+    });
+    if local_worker(profile).send(job).is_err() {
+        return Err(GoError::RuntimeShutdown);
+    }
+    let (join, handle) = joined.await.map_err(|_| GoError::RuntimeShutdown)?;
+    SpawnedTask::new(profile, handle, join, receiver, slot, trace)
+        .wait(&ctx, false)
+        .await
+        .0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go_local;
+    use crate::prelude::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    #[tokio::test]
+    async fn non_send_futures_run_locally() {
+        let r = go_local!(
+            |tx: Sender<i32>| async move {
+                let state = Rc::new(RefCell::new(1));
+                sleep(Duration::from_millis(10)).await;
+                *state.borrow_mut() += 1;
+                let _ = tx.send(*state.borrow());
+            },
+            Context::profile(65)
+        )
+        .await;
+        assert_eq!(r, Ok(2));
+
+        let r = go_local!(
+            |tx: Sender<i32>| async move {
+                let _state = Rc::new(());
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(0);
+            },
+            Context::profile(65).timeout(Duration::from_millis(50))
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+    }
+
+    #[tokio::test]
+    async fn worker_survives_a_panicking_closure() {
+        let fail = true;
+        let r = go_local!(
+            |tx: Sender<i32>| {
+                if fail {
+                    panic!("closure panicked");
+                }
+                async move {
+                    let _ = tx.send(0);
+                }
+            },
+            Context::profile(74)
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Panicked("closure panicked".to_string())));
+        let r = go_local!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(1);
+            },
+            Context::profile(74)
+        )
+        .await;
+        assert_eq!(r, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn local_calls_start_in_dispatch_order() {
+        let started = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let calls = (0..8).map(|i| {
+            let started = started.clone();
+            go_local!(
+                |tx: Sender<usize>| async move {
+                    started.lock().unwrap().push(i);
+                    sleep(Duration::from_millis(10)).await;
+                    let _ = tx.send(i);
+                },
+                Context::profile(66)
+            )
+        });
+        let results = futures::future::join_all(calls).await;
+        assert_eq!(results, (0..8).map(Ok).collect::<Vec<_>>());
+        assert_eq!(*started.lock().unwrap(), (0..8).collect::<Vec<_>>());
+    }
+}
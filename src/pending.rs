@@ -0,0 +1,270 @@
+use crate::cancel::cancel_scope;
+use crate::dispatch::{admit_now, task_failure};
+use crate::prelude::*;
+use crate::stats::OutstandingGuard;
+use crate::trace::TaskTrace;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+use tokio::task::JoinHandle;
+
+/// a task that has already been spawned by `go_spawn!`. await it for the result, or check
+/// without waiting with `try_result`/`go_first_ready!`.
+#[derive(Debug)]
+pub struct Pending<T> {
+    receiver: oneshot::Receiver<T>,
+    profile: u8,
+    /// the runtime the task was spawned on, with its handle
+    join: Option<(Handle, JoinHandle<()>)>,
+    /// error to report instead of a result when the task was never spawned
+    rejected: Option<GoError>,
+    slot: Option<OutstandingGuard>,
+    /// the result channel closed without a value, what's left is to find out why
+    closed: bool,
+    done: bool,
+}
+
+impl<T> Pending<T> {
+    /// the result if the task has finished, `None` if it is still running or the result
+    /// was already taken
+    pub fn try_result(&mut self) -> Option<Result<T, GoError>> {
+        if self.done {
+            return None;
+        }
+        let r = match self.rejected.take() {
+            Some(e) => Err(e),
+            None if self.closed => Err(self.try_closed()?),
+            None => match self.receiver.try_recv() {
+                Ok(v) => Ok(v),
+                Err(TryRecvError::Closed) => {
+                    self.closed = true;
+                    // `None` while the task is still winding down, e.g. unwinding a panic
+                    Err(self.try_closed()?)
+                }
+                Err(TryRecvError::Empty) => return None,
+            },
+        };
+        self.finish();
+        Some(r)
+    }
+
+    /// whether the result has been taken already
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// abort the task, a later result is `GoError::Cancelled`
+    pub fn abort(&self) {
+        if let Some((_, join)) = &self.join {
+            join.abort();
+        }
+    }
+
+    /// `poll_closed` without waiting
+    fn try_closed(&mut self) -> Option<GoError> {
+        let mut cx = TaskContext::from_waker(futures::task::noop_waker_ref());
+        match self.poll_closed(&mut cx) {
+            Poll::Ready(e) => Some(e),
+            Poll::Pending => None,
+        }
+    }
+
+    /// why the result channel closed, like `go!` tells: ready once the task has ended
+    fn poll_closed(&mut self, cx: &mut TaskContext<'_>) -> Poll<GoError> {
+        let (handle, join) = match &mut self.join {
+            Some(task) => task,
+            None => return Poll::Ready(GoError::SenderDropped),
+        };
+        Poll::Ready(match futures::ready!(Pin::new(join).poll(cx)) {
+            Ok(()) => GoError::SenderDropped,
+            Err(e) => task_failure(self.profile, handle, e),
+        })
+    }
+
+    fn finish(&mut self) {
+        self.done = true;
+        self.slot = None;
+    }
+}
+
+impl<T> Future for Pending<T> {
+    type Output = Result<T, GoError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Self::Output> {
+        if let Some(e) = self.rejected.take() {
+            self.finish();
+            return Poll::Ready(Err(e));
+        }
+        if !self.closed {
+            match futures::ready!(Pin::new(&mut self.receiver).poll(cx)) {
+                Ok(v) => {
+                    self.finish();
+                    return Poll::Ready(Ok(v));
+                }
+                Err(_) => self.closed = true,
+            }
+        }
+        let r = Err(futures::ready!(self.poll_closed(cx)));
+        self.finish();
+        Poll::Ready(r)
+    }
+}
+
+/// spawn `f` on `ctx.profile` right away, unlike `go!` which spawns on first poll.
+/// when `set_max_outstanding` is reached nothing is spawned and the handle resolves to
+/// `GoError::Overloaded`, or `GoError::Draining` while the profile is draining
+/// (`GoError::ReservedProfile` on the slot of a named profile picked by number). under
+/// `set_rate_limit` the task waits for its token, or fails fast with `GoError::RateLimited`.
+#[doc(hidden)]
+pub fn go_spawn<T, F, Fut>(ctx: Context, f: F) -> Pending<T>
+where
+    F: FnOnce(Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let profile = ctx.profile;
+    let (sender, receiver) = channel::<T>();
+    match admit_now(&ctx, cancel_scope(f(sender))) {
+        Ok((slot, task)) => {
+            let handle = runtime_handle(profile);
+            let trace = TaskTrace::new(profile, &ctx);
+            let join = handle.spawn(track(profile, trace.watch(task)));
+            Pending {
+                receiver,
+                profile,
+                join: Some((handle, join)),
+                rejected: None,
+                slot: Some(slot),
+                closed: false,
+                done: false,
+            }
+        }
+        Err(e) => Pending {
+            receiver,
+            profile,
+            join: None,
+            rejected: Some(e),
+            slot: None,
+            closed: false,
+            done: false,
+        },
+    }
+}
+
+/// check each of `pending` once, in order, and take the first result that is ready along
+/// with its position. never waits; `None` if all of them are still running.
+pub fn first_ready<'a, T, I>(pending: I) -> Option<(usize, Result<T, GoError>)>
+where
+    I: IntoIterator<Item = &'a mut Pending<T>>,
+    T: 'a,
+{
+    pending
+        .into_iter()
+        .enumerate()
+        .find_map(|(i, p)| p.try_result().map(|r| (i, r)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::{go_first_ready, go_spawn};
+
+    #[tokio::test]
+    async fn first_ready_takes_completed_handle() {
+        let mut slow1 = go_spawn!(22, |tx: Sender<i32>| async move {
+            sleep(Duration::from_secs(5)).await;
+            let _ = tx.send(1);
+        });
+        let mut done = go_spawn!(22, |tx: Sender<i32>| async move {
+            let _ = tx.send(2);
+        });
+        let mut slow2 = go_spawn!(22, |tx: Sender<i32>| async move {
+            sleep(Duration::from_secs(5)).await;
+            let _ = tx.send(3);
+        });
+        assert_eq!(go_first_ready!(slow1, slow2), None);
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(go_first_ready!(slow1, done, slow2), Some((1, Ok(2))));
+        assert!(done.is_done());
+        assert_eq!(go_first_ready!(slow1, done, slow2), None);
+        slow1.abort();
+        assert_eq!(slow1.await, Err(GoError::Cancelled));
+    }
+
+    #[tokio::test]
+    async fn pending_reports_why_the_task_ended() {
+        let mut panicked = go_spawn!(77, |_tx: Sender<i32>| async move {
+            panic!("spawned task panicked");
+        });
+        let mut dropped = go_spawn!(77, |tx: Sender<i32>| async move { drop(tx) });
+        // until the panic hook is done printing, the task counts as running
+        let panicked = loop {
+            match panicked.try_result() {
+                Some(r) => break r,
+                None => sleep(Duration::from_millis(10)).await,
+            }
+        };
+        assert_eq!(
+            panicked,
+            Err(GoError::Panicked("spawned task panicked".to_string()))
+        );
+        sleep(Duration::from_millis(20)).await;
+        assert_eq!(dropped.try_result(), Some(Err(GoError::SenderDropped)));
+
+        let panicked = go_spawn!(77, |_tx: Sender<i32>| async move {
+            panic!("awaited");
+        });
+        assert_eq!(
+            panicked.await,
+            Err(GoError::Panicked("awaited".to_string()))
+        );
+        let slow = go_spawn!(77, |tx: Sender<i32>| async move {
+            sleep(Duration::from_secs(5)).await;
+            let _ = tx.send(1);
+        });
+        sleep(Duration::from_millis(20)).await;
+        shutdown_runtime(77, Duration::ZERO);
+        assert_eq!(slow.await, Err(GoError::RuntimeShutdown));
+    }
+
+    #[tokio::test]
+    async fn outstanding_cap_is_enforced() {
+        use crate::go;
+        set_max_outstanding(31, 2);
+        let spawn_slow = || {
+            go_spawn!(31, |tx: Sender<i32>| async move {
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(1);
+            })
+        };
+        let first = spawn_slow();
+        let _second = spawn_slow();
+        assert_eq!(outstanding(31), 2);
+        let mut third = spawn_slow();
+        assert_eq!(third.try_result(), Some(Err(GoError::Overloaded)));
+        let ctx = Context {
+            profile: 31,
+            ..Default::default()
+        };
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(1);
+            },
+            ctx.clone()
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Overloaded));
+        first.abort();
+        drop(first);
+        assert_eq!(outstanding(31), 1);
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(1);
+            },
+            ctx
+        )
+        .await;
+        assert_eq!(r, Ok(1));
+    }
+}
@@ -0,0 +1,328 @@
+use crate::prelude::*;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+/// a typed name for a runtime slot, e.g. an enum with one variant per workload. anything
+/// implementing it can be passed where the macros take a `Context`, selecting that profile
+/// with default settings. `#[derive(Profile)]` (`derive` feature) covers `#[repr(u8)]` enums.
+pub trait Profile {
+    /// the slot in `RUNTIMES` this profile runs on
+    fn index(&self) -> u8;
+
+    /// whether `index` is the slot of a named profile, which only a `Context` marked
+    /// `named` can run on
+    fn is_named(&self) -> bool {
+        false
+    }
+}
+
+impl Profile for u8 {
+    fn index(&self) -> u8 {
+        *self
+    }
+}
+
+/// names given out by `init_runtime_named`, with their slots
+static NAMES: RwLock<BTreeMap<String, u8>> = RwLock::new(BTreeMap::new());
+
+/// held while a new name gets its slot, so a name racing itself ends up with one
+static NAMING: Mutex<()> = Mutex::new(());
+
+/// the slots in `NAMES`, set before the name's runtime can be seen in its slot
+static RESERVED: [AtomicBool; 256] = [const { AtomicBool::new(false) }; 256];
+
+/// the slot of the named profile `name`, `None` if it was never used
+pub fn named_profile(name: &str) -> Option<u8> {
+    NAMES
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(name)
+        .copied()
+}
+
+/// whether `profile` belongs to a named profile
+pub(crate) fn is_named(profile: u8) -> bool {
+    RESERVED[profile as usize].load(Ordering::Acquire)
+}
+
+/// `GoError::ReservedProfile` if `ctx` picks the slot of a named profile by its number
+pub(crate) fn check_named(ctx: &Context) -> Result<(), GoError> {
+    if !ctx.named && is_named(ctx.profile) {
+        return Err(GoError::ReservedProfile);
+    }
+    Ok(())
+}
+
+/// panics if the numbered `profile` is the slot of a named profile
+pub(crate) fn assert_numbered(profile: u8) {
+    if is_named(profile) {
+        panic!("profile {} is reserved for a named profile", profile);
+    }
+}
+
+/// the slot of the named profile `name`, creating its runtime in the highest free slot on
+/// first use. the same name always maps to the same slot, so code written by different
+/// teams can't end up sharing a runtime by picking the same number. panics if all 256
+/// slots are taken.
+///
+/// like `ScopedProfile`, names take slots from the top down, skipping the runtimes numbered
+/// profiles already created. the slot is then reserved: a `Context` not made from the name
+/// fails with `GoError::ReservedProfile` on it, and `init_runtime` of its number panics.
+/// after the first call a lookup is a read lock and a map search, without allocating.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// let grpc = init_runtime_named("grpc");
+/// assert_eq!(init_runtime_named("grpc"), grpc);
+/// assert_ne!(init_runtime_named("blocking-io"), grpc);
+/// ```
+pub fn init_runtime_named(name: &str) -> u8 {
+    if let Some(profile) = named_profile(name) {
+        return profile;
+    }
+    let _naming = NAMING.lock().unwrap_or_else(PoisonError::into_inner);
+    if let Some(profile) = named_profile(name) {
+        return profile;
+    }
+    loop {
+        let profile = {
+            let slots = runtimes_read();
+            (0..=u8::MAX)
+                .rev()
+                .find(|p| slots[*p as usize].is_none() && !is_named(*p))
+                .expect("no free runtime slot for a named profile")
+        };
+        let build = || RuntimeConfig::default().builder().build().unwrap();
+        // reserved under the slots lock: nothing sees the runtime before it is the name's
+        let reserve = || {
+            RESERVED[profile as usize].store(true, Ordering::Release);
+            NAMES
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(name.to_string(), profile);
+        };
+        // a lazy `go!` or a `ScopedProfile` may take the slot first, then the next is tried
+        if RUNTIMES.install_and(profile, build, reserve) {
+            return profile;
+        }
+    }
+}
+
+/// a name runs on the slot `init_runtime_named` gives it
+impl Profile for &str {
+    fn index(&self) -> u8 {
+        init_runtime_named(self)
+    }
+
+    fn is_named(&self) -> bool {
+        true
+    }
+}
+
+impl Profile for String {
+    fn index(&self) -> u8 {
+        init_runtime_named(self)
+    }
+
+    fn is_named(&self) -> bool {
+        true
+    }
+}
+
+impl Context {
+    /// a `Context` for the named profile `name`, see `init_runtime_named`
+    pub fn named(name: &str) -> Context {
+        Context {
+            profile: init_runtime_named(name),
+            named: true,
+            ..Default::default()
+        }
+    }
+}
+
+impl<P: Profile> From<P> for Context {
+    fn from(profile: P) -> Self {
+        Context {
+            profile: profile.index(),
+            named: profile.is_named(),
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::{go, go_spawn};
+    use futures::StreamExt;
+    use std::collections::HashSet;
+    use std::thread::ThreadId;
+
+    #[derive(Clone, Copy)]
+    enum Workload {
+        Io,
+    }
+
+    impl Profile for Workload {
+        fn index(&self) -> u8 {
+            match self {
+                Workload::Io => 37,
+            }
+        }
+    }
+
+    fn spawned_on(profile: u8) -> u64 {
+        diagnostics()
+            .into_iter()
+            .find(|d| d.profile == profile)
+            .map_or(0, |d| d.spawned)
+    }
+
+    #[tokio::test]
+    async fn go_accepts_profile_enum() {
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(1);
+            },
+            Workload::Io
+        )
+        .await;
+        assert_eq!(r, Ok(1));
+        assert_eq!(spawned_on(37), 1);
+    }
+
+    async fn thread_ids(ctx: Context) -> HashSet<ThreadId> {
+        let mut ids = HashSet::new();
+        for _ in 0..16 {
+            let id = go!(
+                |tx: Sender<ThreadId>| async move {
+                    let _ = tx.send(std::thread::current().id());
+                },
+                ctx.clone()
+            )
+            .await
+            .unwrap();
+            ids.insert(id);
+        }
+        ids
+    }
+
+    #[tokio::test]
+    async fn names_get_their_own_runtimes() {
+        let grpc = Context::named("test-grpc").timeout(Duration::from_secs(1));
+        let io = Context::named("test-io");
+        assert_ne!(grpc.profile, io.profile);
+        assert_eq!(grpc.timeout, Duration::from_secs(1));
+        assert_eq!(init_runtime_named("test-grpc"), grpc.profile);
+        assert_eq!(named_profile("test-io"), Some(io.profile));
+        assert_eq!(named_profile("test-unused"), None);
+
+        let grpc_threads = thread_ids(grpc.clone()).await;
+        let io_threads = thread_ids(io).await;
+        assert!(grpc_threads.is_disjoint(&io_threads));
+        let again = thread_ids("test-grpc".into()).await;
+        assert!(again.is_disjoint(&io_threads));
+        let r = go!(
+            |tx: Sender<u8>| async move {
+                let _ = tx.send(1);
+            },
+            "test-grpc"
+        )
+        .await;
+        assert_eq!(r, Ok(1));
+    }
+
+    #[tokio::test]
+    async fn numbers_cant_use_the_slot_of_a_name() {
+        init_runtime(79);
+        let named = init_runtime_named("test-coexist");
+        assert_ne!(named, 79);
+        let by_number = go!(
+            |tx: Sender<u8>| async move {
+                let _ = tx.send(1);
+            },
+            named
+        )
+        .await;
+        assert_eq!(by_number, Err(GoError::ReservedProfile));
+        assert!(std::panic::catch_unwind(|| init_runtime(named)).is_err());
+        #[cfg(feature = "core_affinity")]
+        assert!(std::panic::catch_unwind(|| init_runtime_pinned(named, &[0])).is_err());
+        let spawned = go_spawn!(named, |tx: Sender<u8>| async move {
+            let _ = tx.send(1);
+        });
+        assert_eq!(spawned.await, Err(GoError::ReservedProfile));
+        let (tx, rx) = channel::<u8>();
+        let with_sender = go!(
+            tx,
+            |tx: Sender<u8>| async move {
+                let _ = tx.send(1);
+            },
+            named
+        );
+        assert_eq!(with_sender.err(), Some(GoError::ReservedProfile));
+        assert!(rx.await.is_err());
+        let produce = |tx: mpsc::Sender<u8>| async move {
+            let _ = tx.send(1).await;
+        };
+        let mut stream = go_stream(produce, named.into());
+        assert_eq!(stream.next().await, None);
+        assert_eq!(stream.finished().await, Err(GoError::ReservedProfile));
+        let mut timed = go_stream_timed(produce, named.into());
+        assert_eq!(timed.next().await, None);
+        assert_eq!(timed.finished().await, Err(GoError::ReservedProfile));
+        let spawned = go_spawn!("test-coexist", |tx: Sender<u8>| async move {
+            let _ = tx.send(1);
+        });
+        assert_eq!(spawned.await, Ok(1));
+        let items: Vec<_> = go_stream(produce, "test-coexist".into()).collect().await;
+        assert_eq!(items, vec![1]);
+
+        let named_threads = thread_ids(Context::named("test-coexist")).await;
+        let numbered_threads = thread_ids(Context::profile(79)).await;
+        assert!(named_threads.is_disjoint(&numbered_threads));
+        assert_eq!(named_profile("test-coexist"), Some(named));
+    }
+
+    #[test]
+    fn racing_callers_of_a_new_name_share_one_slot() {
+        let barrier = std::sync::Arc::new(std::sync::Barrier::new(8));
+        let callers: Vec<_> = (0..8)
+            .map(|_| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    init_runtime_named("test-race")
+                })
+            })
+            .collect();
+        let slots: HashSet<u8> = callers.into_iter().map(|c| c.join().unwrap()).collect();
+        assert_eq!(slots.len(), 1);
+        let slot = *slots.iter().next().unwrap();
+        assert!(RUNTIMES.cached_handle(slot).is_some());
+        assert_eq!(named_profile("test-race"), Some(slot));
+    }
+
+    #[cfg(feature = "derive")]
+    #[tokio::test]
+    async fn derived_profile_uses_discriminant() {
+        #[derive(Profile)]
+        #[repr(u8)]
+        enum Derived {
+            #[allow(dead_code)]
+            Default = 0,
+            Batch = 38,
+        }
+        assert_eq!(Derived::Batch.index(), 38);
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(2);
+            },
+            Derived::Batch
+        )
+        .await;
+        assert_eq!(r, Ok(2));
+        assert_eq!(spawned_on(38), 1);
+    }
+}
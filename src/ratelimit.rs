@@ -0,0 +1,263 @@
+use crate::prelude::*;
+use std::future::Future;
+use std::sync::{Mutex, PoisonError};
+use tokio::time::Instant;
+
+const NANOS_PER_SEC: u128 = 1_000_000_000;
+
+/// a token bucket holding up to `per_sec` tokens, refilled at `per_sec` tokens a second.
+/// it is refilled whenever a token is taken, by what was earned since the last time, so
+/// it keeps counting without a task of its own
+#[derive(Debug)]
+struct Bucket {
+    per_sec: u32,
+    tokens: Mutex<Tokens>,
+}
+
+#[derive(Debug)]
+struct Tokens {
+    available: u64,
+    /// token nanoseconds earned but not yet a whole token
+    earned: u128,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn new(per_sec: u32) -> Self {
+        Bucket {
+            per_sec,
+            tokens: Mutex::new(Tokens {
+                available: per_sec as u64,
+                earned: 0,
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// take a token, or tell how long until the next one is earned
+    fn try_take(&self) -> Result<(), Duration> {
+        let mut t = self.tokens.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+        let per_sec = self.per_sec as u128;
+        let capacity = self.per_sec as u64;
+        t.earned += now.duration_since(t.refilled_at).as_nanos() * per_sec;
+        t.refilled_at = now;
+        let whole = t.earned / NANOS_PER_SEC;
+        t.earned %= NANOS_PER_SEC;
+        t.available = (t.available as u128 + whole).min(capacity as u128) as u64;
+        if t.available == capacity {
+            t.earned = 0;
+        }
+        if t.available > 0 {
+            t.available -= 1;
+            return Ok(());
+        }
+        let missing = NANOS_PER_SEC - t.earned;
+        Err(Duration::from_nanos(missing.div_ceil(per_sec) as u64))
+    }
+}
+
+static BUCKETS: [RwLock<Option<Arc<Bucket>>>; 256] = [const { RwLock::new(None) }; 256];
+
+fn bucket(profile: u8) -> Option<Arc<Bucket>> {
+    BUCKETS[profile as usize]
+        .read()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// cap the rate tasks are spawned at on `profile` to `per_sec`, with bursts of up to
+/// `per_sec` tasks. a dispatch beyond the rate waits for a token, or fails with
+/// `GoError::RateLimited` when its `Context.fail_fast_on_rate_limit` is set. calls that spawn
+/// right away (`go_spawn!`, `go_stream!`, `go!` with a sender) start their task when the
+/// token arrives instead, or fail fast without spawning. tokens are
+/// counted from the time that passed whenever one is taken, so any rate up to `u32::MAX`
+/// is kept and the limit outlives `shutdown_runtime` of the profile. 0 removes the limit;
+/// calls waiting on a replaced limit go through.
+pub fn set_rate_limit(profile: u8, per_sec: u32) {
+    let new = (per_sec > 0).then(|| Arc::new(Bucket::new(per_sec)));
+    *BUCKETS[profile as usize]
+        .write()
+        .unwrap_or_else(PoisonError::into_inner) = new;
+}
+
+/// remove the limit set with `set_rate_limit`
+pub fn clear_rate_limit(profile: u8) {
+    set_rate_limit(profile, 0);
+}
+
+/// take a token for one spawn on `profile`, waiting for it unless `fail_fast`
+pub(crate) async fn take_token(profile: u8, fail_fast: bool) -> Result<(), GoError> {
+    if fail_fast {
+        return try_token(profile);
+    }
+    let bucket = match bucket(profile) {
+        Some(bucket) => bucket,
+        None => return Ok(()),
+    };
+    loop {
+        match bucket.try_take() {
+            Ok(()) => return Ok(()),
+            Err(wait) => sleep(wait).await,
+        }
+        match self::bucket(profile) {
+            Some(current) if Arc::ptr_eq(&current, &bucket) => {}
+            // the limit was replaced or removed while waiting
+            _ => return Ok(()),
+        }
+    }
+}
+
+/// take a token for one spawn on `profile` if there is one, `GoError::RateLimited` otherwise
+fn try_token(profile: u8) -> Result<(), GoError> {
+    match bucket(profile) {
+        Some(bucket) => bucket.try_take().map_err(|_| GoError::RateLimited),
+        None => Ok(()),
+    }
+}
+
+/// `take_token` for a spawn that can't wait in the caller: with `fail_fast` the token is
+/// taken right away, otherwise `fut` waits for it before it starts
+pub(crate) fn throttle<F: Future>(
+    profile: u8,
+    fail_fast: bool,
+    fut: F,
+) -> Result<impl Future<Output = F::Output>, GoError> {
+    if fail_fast {
+        try_token(profile)?;
+    }
+    Ok(async move {
+        if !fail_fast {
+            let _ = take_token(profile, false).await;
+        }
+        fut.await
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go;
+    use crate::prelude::*;
+    use std::time::Instant;
+
+    async fn call(ctx: Context) -> Result<(), GoError> {
+        go!(
+            |tx: Sender<()>| async move {
+                let _ = tx.send(());
+            },
+            ctx
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn bursts_beyond_rate_are_throttled_or_rejected() {
+        set_rate_limit(55, 10);
+        let waiting = Context {
+            profile: 55,
+            ..Default::default()
+        };
+        let rejecting = Context {
+            fail_fast_on_rate_limit: true,
+            ..waiting.clone()
+        };
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert_eq!(call(rejecting.clone()).await, Ok(()));
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(call(rejecting.clone()).await, Err(GoError::RateLimited));
+        for _ in 0..4 {
+            assert_eq!(call(waiting.clone()).await, Ok(()));
+        }
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        clear_rate_limit(55);
+        assert_eq!(call(rejecting).await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn high_rates_refill_beyond_the_timer_resolution() {
+        set_rate_limit(71, 100_000);
+        let bucket = super::bucket(71).unwrap();
+        bucket.tokens.lock().unwrap().available = 0;
+        sleep(Duration::from_millis(50)).await;
+        let mut taken = 0;
+        while bucket.try_take().is_ok() {
+            taken += 1;
+        }
+        // one token per timer tick would give about 50
+        assert!(taken >= 1_000);
+        clear_rate_limit(71);
+
+        set_rate_limit(72, u32::MAX);
+        let bucket = super::bucket(72).unwrap();
+        bucket.tokens.lock().unwrap().available = 0;
+        sleep(Duration::from_millis(50)).await;
+        assert_eq!(bucket.try_take(), Ok(()));
+        assert!(bucket.tokens.lock().unwrap().available < u32::MAX as u64);
+        clear_rate_limit(72);
+    }
+
+    #[tokio::test]
+    async fn limit_keeps_refilling_after_a_runtime_restart() {
+        set_rate_limit(82, 20);
+        let ctx = Context::profile(82);
+        for _ in 0..20 {
+            assert_eq!(call(ctx.clone()).await, Ok(()));
+        }
+        shutdown_runtime(82, Duration::ZERO);
+        init_runtime(82);
+        let waited = tokio::time::timeout(Duration::from_secs(1), call(ctx.clone())).await;
+        assert_eq!(waited, Ok(Ok(())));
+        sleep(Duration::from_millis(100)).await;
+        let rejecting = Context {
+            fail_fast_on_rate_limit: true,
+            ..ctx
+        };
+        assert_eq!(call(rejecting).await, Ok(()));
+        clear_rate_limit(82);
+    }
+
+    #[tokio::test]
+    async fn spawning_right_away_is_limited_too() {
+        use crate::go_spawn;
+        use futures::StreamExt;
+        set_rate_limit(86, 10);
+        let waiting = Context::profile(86);
+        let rejecting = Context {
+            fail_fast_on_rate_limit: true,
+            ..waiting.clone()
+        };
+        for _ in 0..10 {
+            assert_eq!(call(rejecting.clone()).await, Ok(()));
+        }
+        let spawned = go_spawn!(rejecting.clone(), |tx: Sender<()>| async move {
+            let _ = tx.send(());
+        });
+        assert_eq!(spawned.await, Err(GoError::RateLimited));
+        let produce = |tx: mpsc::Sender<()>| async move {
+            let _ = tx.send(()).await;
+        };
+        let stream = go_stream(produce, rejecting.clone());
+        assert_eq!(stream.finished().await, Err(GoError::RateLimited));
+        let (tx, _rx) = channel::<()>();
+        let sent = go!(
+            tx,
+            |tx: Sender<()>| async move {
+                let _ = tx.send(());
+            },
+            rejecting
+        );
+        assert_eq!(sent.err(), Some(GoError::RateLimited));
+
+        let start = Instant::now();
+        let spawned = go_spawn!(waiting.clone(), |tx: Sender<()>| async move {
+            let _ = tx.send(());
+        });
+        assert_eq!(spawned.await, Ok(()));
+        let items: Vec<()> = go_stream(produce, waiting).collect().await;
+        assert_eq!(items, vec![()]);
+        assert!(start.elapsed() >= Duration::from_millis(150));
+        clear_rate_limit(86);
+    }
+}
@@ -0,0 +1,486 @@
+use crate::dispatch::dispatch_on;
+use crate::prelude::*;
+use arc_swap::ArcSwapOption;
+use std::future::Future;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError, RwLockReadGuard, RwLockWriteGuard};
+use tokio::runtime::Builder;
+
+static DEFAULT_WORKER_THREADS: AtomicUsize = AtomicUsize::new(0);
+
+/// build every runtime created without an explicit worker count (by `init_runtime`, a lazy
+/// `go!`, or `init_runtime_with` and `configure_from` leaving `worker_threads` unset) with
+/// `n` workers. 0 goes back to tokio's default of one per core. runtimes that already exist
+/// keep their workers.
+pub fn set_default_worker_threads(n: usize) {
+    DEFAULT_WORKER_THREADS.store(n, Ordering::Relaxed);
+}
+
+/// how the tasks of a profile are scheduled, see `RuntimeConfig`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RuntimeFlavor {
+    /// tokio's work stealing scheduler over `worker_threads` threads
+    #[default]
+    MultiThread,
+    /// a multi thread runtime with a single worker, `worker_threads` is ignored: every task
+    /// of the profile runs on that one thread, with no other one to steal them. it is not a
+    /// tokio `current_thread` runtime, which only makes progress inside `Runtime::block_on`
+    /// and so can't serve a profile that `go!` spawns onto from other threads.
+    SingleWorker,
+}
+
+/// the settings a profile's runtime is built with by `init_runtime_with`. fields left
+/// `None` keep the crate's or tokio's defaults.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct RuntimeConfig {
+    pub flavor: RuntimeFlavor,
+    /// `set_default_worker_threads` (or tokio's default) when `None`
+    pub worker_threads: Option<usize>,
+    pub max_blocking_threads: Option<usize>,
+    /// worker and blocking threads are named `<prefix>-<n>`, tokio's `tokio-runtime-worker`
+    /// when `None`
+    pub thread_name_prefix: Option<String>,
+    /// in bytes, tokio's default (2 MiB) when `None`
+    pub thread_stack_size: Option<usize>,
+}
+
+impl RuntimeConfig {
+    /// a builder with all drivers and these settings applied
+    pub(crate) fn builder(&self) -> Builder {
+        let mut builder = Builder::new_multi_thread();
+        builder.enable_all();
+        let workers = match self.flavor {
+            RuntimeFlavor::SingleWorker => Some(1),
+            RuntimeFlavor::MultiThread => {
+                self.worker_threads
+                    .or(match DEFAULT_WORKER_THREADS.load(Ordering::Relaxed) {
+                        0 => None,
+                        n => Some(n),
+                    })
+            }
+        };
+        if let Some(n) = workers {
+            builder.worker_threads(n);
+        }
+        if let Some(n) = self.max_blocking_threads {
+            builder.max_blocking_threads(n);
+        }
+        if let Some(prefix) = self.thread_name_prefix.clone() {
+            let next = AtomicUsize::new(0);
+            builder.thread_name_fn(move || {
+                format!("{}-{}", prefix, next.fetch_add(1, Ordering::Relaxed))
+            });
+        }
+        if let Some(size) = self.thread_stack_size {
+            builder.thread_stack_size(size);
+        }
+        builder
+    }
+}
+
+/// `N` runtime slots, each created on first use. the global `RUNTIMES` behind `go!` is a
+/// `Registry<256>`; a smaller one saves the footprint of unused slots.
+///
+/// profiles are `u8`, so `N` is checked at compile time to be between 1 and 256. counters,
+/// observers and hooks are keyed by profile number and shared with the global registry.
+///
+/// the `RwLock` of runtimes is the source of truth for their lifecycle, dispatch reads a
+/// lock-free snapshot of their handles instead. the snapshot is refreshed by every change
+/// made through this crate; a runtime taken out by writing the lock directly may still be
+/// spawned on until the next change.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// static SMALL: Registry<2> = Registry::new();
+/// #[tokio::main]
+/// async fn main(){
+/// let r = SMALL.go(|tx: Sender<i32>|async move{
+///     let _ = tx.send(1);
+/// },
+/// Context{
+///     profile: 1,
+///     ..Default::default()
+/// }
+/// ).await;
+/// assert_eq!(r, Ok(1));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Registry<const N: usize> {
+    slots: RwLock<[Option<Runtime>; N]>,
+    handles: ArcSwapOption<[Option<Handle>; N]>,
+    /// serializes the creation of each slot's runtime, so racing first calls build only one
+    init: [Mutex<()>; N],
+}
+
+impl<const N: usize> Registry<N> {
+    const SIZE_OK: () = assert!(N >= 1 && N <= 256, "a Registry holds 1 to 256 profiles");
+
+    /// a registry with every slot empty
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let _ = Self::SIZE_OK;
+        Registry {
+            slots: RwLock::new([const { None }; N]),
+            handles: ArcSwapOption::const_empty(),
+            init: [const { Mutex::new(()) }; N],
+        }
+    }
+
+    /// read the slots, also after a panic elsewhere poisoned the lock: slots are only ever
+    /// replaced as a whole, so they stay consistent and one failure can't brick the registry
+    pub(crate) fn read_slots(&self) -> RwLockReadGuard<'_, [Option<Runtime>; N]> {
+        self.slots.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// write the slots, tolerating poisoning like `read_slots`. the handle snapshot is
+    /// refreshed when the guard is dropped
+    pub(crate) fn write_slots(&self) -> SlotsWriteGuard<'_, N> {
+        SlotsWriteGuard {
+            slots: self.slots.write().unwrap_or_else(PoisonError::into_inner),
+            handles: &self.handles,
+        }
+    }
+
+    /// the handle of `profile` in the snapshot, without touching the lock
+    pub(crate) fn cached_handle(&self, profile: u8) -> Option<Handle> {
+        self.handles.load().as_ref()?[Self::slot(profile)].clone()
+    }
+
+    fn slot(profile: u8) -> usize {
+        let i = profile as usize;
+        assert!(
+            i < N,
+            "profile {} out of range for a Registry<{}>",
+            profile,
+            N
+        );
+        i
+    }
+
+    /// create the runtime of `profile` unless it exists. panics if `profile >= N`
+    pub fn init_runtime(&self, profile: u8) {
+        self.init_runtime_with(profile, RuntimeConfig::default())
+    }
+
+    /// create the runtime of `profile` with `cfg` unless it exists. panics if `profile >= N`.
+    /// concurrent calls for the same profile build it exactly once.
+    pub fn init_runtime_with(&self, profile: u8, cfg: RuntimeConfig) {
+        self.install_with(profile, || cfg.builder().build().unwrap());
+    }
+
+    /// install the runtime `build` makes in the slot of `profile` unless the slot is taken,
+    /// returning whether it was. every runtime this crate creates goes through here, so
+    /// racing creators of one slot build only one runtime and the snapshot stays current.
+    pub(crate) fn install_with(&self, profile: u8, build: impl FnOnce() -> Runtime) -> bool {
+        self.install_and(profile, build, || {})
+    }
+
+    /// `install_with`, calling `installed` under the slots lock right after the runtime went
+    /// in, before anyone can see it
+    pub(crate) fn install_and(
+        &self,
+        profile: u8,
+        build: impl FnOnce() -> Runtime,
+        installed: impl FnOnce(),
+    ) -> bool {
+        let i = Self::slot(profile);
+        if self.read_slots()[i].is_some() {
+            return false;
+        }
+        let _init = self.init[i].lock().unwrap_or_else(PoisonError::into_inner);
+        if self.read_slots()[i].is_some() {
+            return false;
+        }
+        // build outside the slots lock: a panic while holding it would poison it
+        let runtime = build();
+        #[cfg(test)]
+        tests::BUILT[i].fetch_add(1, Ordering::Relaxed);
+        let mut w = self.write_slots();
+        if w[i].is_none() {
+            w[i] = Some(runtime);
+            installed();
+            true
+        } else {
+            // installed behind our back through the lock itself
+            drop(w);
+            runtime.shutdown_background();
+            false
+        }
+    }
+
+    /// take the runtime of `profile` out of its slot and shut it down, giving its blocking
+    /// threads up to `grace` to finish. panics if `profile >= N`, see `shutdown_runtime`
+    pub fn shutdown_runtime(&self, profile: u8, grace: Duration) {
+        let runtime = self.write_slots()[Self::slot(profile)].take();
+        shut_down(runtime.into_iter().collect(), grace);
+    }
+
+    /// `shutdown_runtime` for every profile at once, all within the same `grace`
+    pub fn shutdown_all(&self, grace: Duration) {
+        let runtimes = self
+            .write_slots()
+            .iter_mut()
+            .filter_map(Option::take)
+            .collect();
+        shut_down(runtimes, grace);
+    }
+
+    /// initialize the runtime of `profile` if needed and return a cloned handle to it,
+    /// so that callers don't keep the registry locked while spawning or awaiting
+    pub fn runtime_handle(&self, profile: u8) -> Handle {
+        if let Some(handle) = self.cached_handle(profile) {
+            return handle;
+        }
+        self.init_runtime(profile);
+        let handle = self.read_slots()[Self::slot(profile)]
+            .as_ref()
+            .unwrap()
+            .handle()
+            .clone();
+        if self.cached_handle(profile).is_none() {
+            // installed behind our back through the lock itself
+            drop(self.write_slots());
+        }
+        handle
+    }
+
+    /// `go!` on this registry: run `f` on the runtime of `ctx.profile` honouring `ctx`
+    pub fn go<T, F, Fut>(&self, f: F, ctx: Context) -> impl Future<Output = Result<T, GoError>>
+    where
+        F: Fn(Sender<T>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+        T: Send + 'static,
+    {
+        GoFuture::new(dispatch_on(self.runtime_handle(ctx.profile), f, ctx))
+    }
+}
+
+/// shut `runtimes` down in parallel. `shutdown_timeout` blocks, which tokio refuses to do on
+/// a runtime thread, so each runs on a thread of its own and the caller waits for all of them
+fn shut_down(runtimes: Vec<Runtime>, grace: Duration) {
+    let threads: Vec<_> = runtimes
+        .into_iter()
+        .map(|runtime| std::thread::spawn(move || runtime.shutdown_timeout(grace)))
+        .collect();
+    for thread in threads {
+        let _ = thread.join();
+    }
+}
+
+/// write access to the runtimes of a `Registry` that publishes a new handle snapshot when dropped
+pub(crate) struct SlotsWriteGuard<'a, const N: usize> {
+    slots: RwLockWriteGuard<'a, [Option<Runtime>; N]>,
+    handles: &'a ArcSwapOption<[Option<Handle>; N]>,
+}
+
+impl<const N: usize> Deref for SlotsWriteGuard<'_, N> {
+    type Target = [Option<Runtime>; N];
+
+    fn deref(&self) -> &Self::Target {
+        &self.slots
+    }
+}
+
+impl<const N: usize> DerefMut for SlotsWriteGuard<'_, N> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.slots
+    }
+}
+
+impl<const N: usize> Drop for SlotsWriteGuard<'_, N> {
+    fn drop(&mut self) {
+        let snapshot = std::array::from_fn(|i| self.slots[i].as_ref().map(|r| r.handle().clone()));
+        self.handles.store(Some(Arc::new(snapshot)));
+    }
+}
+
+impl<const N: usize> Default for Registry<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> Deref for Registry<N> {
+    type Target = RwLock<[Option<Runtime>; N]>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.slots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go;
+    use crate::prelude::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Barrier;
+
+    /// runtimes built per slot index, across all registries
+    pub(super) static BUILT: [AtomicUsize; 256] = [const { AtomicUsize::new(0) }; 256];
+
+    static SMALL: Registry<4> = Registry::new();
+
+    #[tokio::test]
+    async fn small_registry_runs_tasks() {
+        assert!(SMALL.read().unwrap().iter().all(|s| s.is_none()));
+        let r = SMALL
+            .go(
+                |tx: Sender<i32>| async move {
+                    let _ = tx.send(3);
+                },
+                Context {
+                    profile: 3,
+                    ..Default::default()
+                },
+            )
+            .await;
+        assert_eq!(r, Ok(3));
+        assert!(SMALL.read().unwrap()[3].is_some());
+        assert_eq!(
+            std::mem::size_of_val(&*SMALL.read().unwrap()),
+            4 * std::mem::size_of::<Option<Runtime>>()
+        );
+    }
+
+    #[test]
+    fn snapshot_follows_runtimes() {
+        let registry: Registry<4> = Registry::new();
+        assert!(registry.cached_handle(2).is_none());
+        registry.init_runtime(2);
+        assert!(registry.cached_handle(2).is_some());
+        assert!(registry.cached_handle(1).is_none());
+
+        let scoped = ScopedProfile::new(1);
+        let profile = scoped.profile();
+        assert!(RUNTIMES.cached_handle(profile).is_some());
+        drop(scoped);
+        assert!(RUNTIMES.cached_handle(profile).is_none());
+    }
+
+    #[tokio::test]
+    async fn lazy_profiles_use_default_workers() {
+        set_default_worker_threads(1);
+        init_runtime(53);
+        set_default_worker_threads(0);
+        let d = diagnostics().into_iter().find(|d| d.profile == 53).unwrap();
+        assert_eq!(d.workers, Some(1));
+    }
+
+    #[tokio::test]
+    async fn configured_runtime_names_its_threads() {
+        init_runtime_with(
+            68,
+            RuntimeConfig {
+                worker_threads: Some(1),
+                thread_name_prefix: Some("go-io".to_string()),
+                thread_stack_size: Some(4 << 20),
+                ..Default::default()
+            },
+        );
+        // already initialized, so this one is ignored
+        init_runtime_with(
+            68,
+            RuntimeConfig {
+                worker_threads: Some(4),
+                ..Default::default()
+            },
+        );
+        let name = go!(
+            |tx: Sender<Option<String>>| async move {
+                let _ = tx.send(std::thread::current().name().map(str::to_string));
+            },
+            Context {
+                profile: 68,
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(name, Ok(Some("go-io-0".to_string())));
+        let d = diagnostics().into_iter().find(|d| d.profile == 68).unwrap();
+        assert_eq!(d.workers, Some(1));
+    }
+
+    #[tokio::test]
+    async fn single_worker_flavor_runs_on_one_thread() {
+        init_runtime_with(
+            57,
+            RuntimeConfig {
+                flavor: RuntimeFlavor::SingleWorker,
+                worker_threads: Some(8),
+                ..Default::default()
+            },
+        );
+        let d = diagnostics().into_iter().find(|d| d.profile == 57).unwrap();
+        assert_eq!(d.workers, Some(1));
+    }
+
+    #[tokio::test]
+    async fn shutdown_fails_pending_calls_and_allows_reinit() {
+        let ctx = Context {
+            profile: 3,
+            ..Default::default()
+        };
+        let pending = tokio::spawn(go!(
+            |tx: Sender<i32>| async move {
+                sleep(Duration::from_secs(10)).await;
+                let _ = tx.send(1);
+            },
+            ctx.clone()
+        ));
+        sleep(Duration::from_millis(50)).await;
+        shutdown_runtime(3, Duration::from_millis(100));
+        assert!(RUNTIMES.read_slots()[3].is_none());
+        assert_eq!(pending.await.unwrap(), Err(GoError::RuntimeShutdown));
+
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(2);
+            },
+            ctx
+        )
+        .await;
+        assert_eq!(r, Ok(2));
+        shutdown_runtime(59, Duration::from_millis(100));
+    }
+
+    #[test]
+    fn shutdown_all_empties_every_slot() {
+        let registry: Registry<4> = Registry::new();
+        registry.init_runtime(0);
+        registry.init_runtime(3);
+        registry.shutdown_all(Duration::from_millis(100));
+        assert!(registry.read().unwrap().iter().all(|s| s.is_none()));
+        assert!(registry.cached_handle(3).is_none());
+        registry.init_runtime(3);
+        assert!(registry.cached_handle(3).is_some());
+    }
+
+    #[test]
+    fn racing_first_calls_build_one_runtime() {
+        let barrier = Arc::new(Barrier::new(32));
+        let threads: Vec<_> = (0..32)
+            .map(|_| {
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    runtime_handle(60).block_on(async { 1 })
+                })
+            })
+            .collect();
+        for t in threads {
+            assert_eq!(t.join().unwrap(), 1);
+        }
+        assert_eq!(BUILT[60].load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn profile_beyond_size_panics() {
+        SMALL.init_runtime(4);
+    }
+}
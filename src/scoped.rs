@@ -0,0 +1,115 @@
+use crate::prelude::*;
+use crate::profile::is_named;
+
+/// a runtime that lives in a free `RUNTIMES` slot for as long as the guard is alive.
+/// dropping the guard shuts the runtime down (without waiting for its tasks) and frees the slot.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tokio_go::go;
+/// #[tokio::main]
+/// async fn main(){
+/// let scoped = ScopedProfile::new(1);
+/// let r = go!(|tx: Sender<i32>|async move{
+///     let _ = tx.send(1);
+/// },
+/// Context{
+///     profile: scoped.profile(),
+///     ..Default::default()
+/// }
+/// ).await;
+/// assert_eq!(r, Ok(1));
+/// }
+/// ```
+#[derive(Debug)]
+pub struct ScopedProfile {
+    profile: u8,
+}
+
+impl ScopedProfile {
+    /// build a runtime with `worker_threads` workers in the highest free slot not taken by a
+    /// named profile.
+    /// panics if all 256 slots are taken.
+    pub fn new(worker_threads: usize) -> ScopedProfile {
+        let cfg = RuntimeConfig {
+            worker_threads: Some(worker_threads),
+            ..Default::default()
+        };
+        loop {
+            let profile = {
+                let slots = runtimes_read();
+                (0..=u8::MAX)
+                    .rev()
+                    .find(|p| slots[*p as usize].is_none() && !is_named(*p))
+                    .expect("no free runtime slot for ScopedProfile")
+            };
+            // a lazy `go!` may take the slot first, then the next free one is tried
+            if RUNTIMES.install_with(profile, || cfg.builder().build().unwrap()) {
+                return ScopedProfile { profile };
+            }
+        }
+    }
+
+    /// the slot to pass as `Context.profile`
+    pub fn profile(&self) -> u8 {
+        self.profile
+    }
+}
+
+impl Drop for ScopedProfile {
+    fn drop(&mut self) {
+        let runtime = runtimes_write()[self.profile as usize].take();
+        if let Some(runtime) = runtime {
+            runtime.shutdown_background();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go;
+    use crate::prelude::*;
+
+    #[tokio::test]
+    async fn slot_freed_after_drop() {
+        let scoped = ScopedProfile::new(1);
+        let profile = scoped.profile();
+        let r = go!(
+            |tx: Sender<usize>| async move {
+                let _ = tx.send(Handle::current().metrics().num_workers());
+            },
+            Context {
+                profile,
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r, Ok(1));
+        assert!(runtimes_read()[profile as usize].is_some());
+        drop(scoped);
+        assert!(runtimes_read()[profile as usize].is_none());
+    }
+
+    #[test]
+    fn concurrent_scopes_get_their_own_slots() {
+        let barrier = std::sync::Barrier::new(8);
+        let scopes: Vec<ScopedProfile> = std::thread::scope(|s| {
+            let threads: Vec<_> = (0..8)
+                .map(|_| {
+                    s.spawn(|| {
+                        barrier.wait();
+                        ScopedProfile::new(1)
+                    })
+                })
+                .collect();
+            threads.into_iter().map(|t| t.join().unwrap()).collect()
+        });
+        let mut profiles: Vec<u8> = scopes.iter().map(|s| s.profile()).collect();
+        profiles.sort();
+        profiles.dedup();
+        assert_eq!(profiles.len(), 8);
+        for p in &profiles {
+            assert!(runtimes_read()[*p as usize].is_some());
+        }
+    }
+}
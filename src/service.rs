@@ -0,0 +1,110 @@
+use crate::prelude::*;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use tower_service::Service;
+
+type Handler<Req, Resp> =
+    Arc<dyn Fn(Req, Sender<Resp>) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
+/// a `tower::Service` that runs a `go!` style closure on a profile for every request, so
+/// goroutine backed work can sit behind tower middleware (timeouts, retries, load shedding).
+/// each `call` spawns `f(request, sender)` according to the service's `Context` and fails
+/// with the `GoError` the dispatch ends with. `ctx.auto_retry` is ignored, as a request is
+/// consumed by its call; use tower's retry middleware instead.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use tower::ServiceExt;
+/// #[tokio::main]
+/// async fn main(){
+/// let service = GoService::new(Context::default(), |x: i32, tx: Sender<i32>| async move {
+///     let _ = tx.send(x + 1);
+/// });
+/// assert_eq!(service.oneshot(1).await, Ok(2));
+/// }
+/// ```
+pub struct GoService<Req, Resp> {
+    handler: Handler<Req, Resp>,
+    ctx: Context,
+}
+
+impl<Req, Resp> GoService<Req, Resp> {
+    /// serve requests with `f` on `ctx.profile`
+    pub fn new<F, Fut>(ctx: Context, f: F) -> Self
+    where
+        F: Fn(Req, Sender<Resp>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        GoService {
+            handler: Arc::new(move |req, tx| Box::pin(f(req, tx))),
+            ctx,
+        }
+    }
+}
+
+impl<Req, Resp> Clone for GoService<Req, Resp> {
+    fn clone(&self) -> Self {
+        GoService {
+            handler: self.handler.clone(),
+            ctx: self.ctx.clone(),
+        }
+    }
+}
+
+impl<Req, Resp> fmt::Debug for GoService<Req, Resp> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GoService").field("ctx", &self.ctx).finish()
+    }
+}
+
+impl<Req, Resp> Service<Req> for GoService<Req, Resp>
+where
+    Req: Send + 'static,
+    Resp: Send + 'static,
+{
+    type Response = Resp;
+    type Error = GoError;
+    type Future = Pin<Box<dyn Future<Output = Result<Resp, GoError>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut TaskContext<'_>) -> Poll<Result<(), GoError>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let handler = self.handler.clone();
+        Box::pin(dispatch_once(
+            move |tx: Sender<Resp>| handler(req, tx),
+            self.ctx.clone(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use tower::ServiceExt;
+
+    #[tokio::test]
+    async fn requests_run_through_service() {
+        let service = GoService::new(
+            Context {
+                profile: 48,
+                timeout: Duration::from_millis(100),
+                ..Default::default()
+            },
+            |len: usize, tx: Sender<String>| async move {
+                if len > 3 {
+                    sleep(Duration::from_secs(5)).await;
+                }
+                let _ = tx.send("x".repeat(len));
+            },
+        );
+        assert_eq!(service.clone().oneshot(2).await, Ok("xx".to_string()));
+        assert_eq!(
+            service.oneshot(4).await,
+            Err(GoError::Timeout { attempts: 1 })
+        );
+    }
+}
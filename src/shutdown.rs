@@ -0,0 +1,157 @@
+use crate::stats::in_flight;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+lazy_static! {
+    static ref SHUTDOWN: CancellationToken = CancellationToken::new();
+}
+
+/// the crate wide shutdown token. tasks dispatched with `Context.observe_global_shutdown` are
+/// cancelled once it fires; other code may observe it too.
+pub fn shutdown_token() -> CancellationToken {
+    SHUTDOWN.clone()
+}
+
+/// fire the shutdown token: observing tasks are aborted and return `GoError::Cancelled`.
+/// this can't be undone.
+pub fn trigger_shutdown() {
+    SHUTDOWN.cancel();
+}
+
+static DRAINING: [AtomicBool; 256] = [const { AtomicBool::new(false) }; 256];
+
+/// stop (or resume) accepting new work on `profile`: while draining, new `go!` calls (also
+/// with a sender), `go_spawn`s and blocking calls on it fail with `GoError::Draining` without
+/// spawning, and new `go_stream!`s end right away with `finished` telling why. tasks already
+/// in flight run to completion. pair it with `drain` for restarts that drop nothing.
+pub fn set_draining(profile: u8, draining: bool) {
+    DRAINING[profile as usize].store(draining, Ordering::Relaxed);
+}
+
+/// whether `profile` was marked with `set_draining`
+pub fn is_draining(profile: u8) -> bool {
+    DRAINING[profile as usize].load(Ordering::Relaxed)
+}
+
+/// wait until no task spawned through this crate is in flight on `profile`, for at most
+/// `grace`. returns whether everything finished in time. doesn't stop new tasks from
+/// coming in by itself, see `set_draining`.
+pub async fn drain(profile: u8, grace: Duration) -> bool {
+    wait_idle(grace, || in_flight(profile) == 0).await
+}
+
+/// wait until no task spawned through this crate is in flight on any profile, for at most
+/// `grace`. returns whether everything finished in time.
+pub async fn drain_all(grace: Duration) -> bool {
+    wait_idle(grace, || (0..=u8::MAX).all(|p| in_flight(p) == 0)).await
+}
+
+async fn wait_idle(grace: Duration, idle: impl Fn() -> bool) -> bool {
+    let deadline = Instant::now() + grace;
+    loop {
+        if idle() {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go;
+    use crate::go_spawn;
+    use crate::prelude::*;
+    use std::time::Instant;
+
+    #[tokio::test]
+    async fn draining_rejects_new_and_finishes_running() {
+        let running = go_spawn!(50, |tx: Sender<i32>| async move {
+            sleep(Duration::from_millis(200)).await;
+            let _ = tx.send(1);
+        });
+        set_draining(50, true);
+        let ctx = Context {
+            profile: 50,
+            ..Default::default()
+        };
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(2);
+            },
+            ctx.clone()
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Draining));
+        assert!(drain(50, Duration::from_secs(2)).await);
+        assert_eq!(running.await, Ok(1));
+        set_draining(50, false);
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(3);
+            },
+            ctx
+        )
+        .await;
+        assert_eq!(r, Ok(3));
+    }
+
+    #[tokio::test]
+    async fn shutdown_cancels_observing_task() {
+        let start = Instant::now();
+        let call = go!(
+            |tx: Sender<()>| async move {
+                sleep(Duration::from_secs(10)).await;
+                let _ = tx.send(());
+            },
+            Context {
+                profile: 28,
+                observe_global_shutdown: true,
+                ..Default::default()
+            }
+        );
+        let trigger = async {
+            sleep(Duration::from_millis(50)).await;
+            trigger_shutdown();
+        };
+        let (r, _) = tokio::join!(call, trigger);
+        assert_eq!(r, Err(GoError::Cancelled));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(shutdown_token().is_cancelled());
+        drain_all(Duration::from_millis(200)).await;
+        assert_eq!(in_flight(28), 0);
+    }
+
+    #[tokio::test]
+    async fn draining_rejects_streams_senders_and_blocking_calls() {
+        use futures::StreamExt;
+        set_draining(85, true);
+        let produce = |tx: mpsc::Sender<i32>| async move {
+            let _ = tx.send(1).await;
+        };
+        let mut stream = go_stream(produce, Context::profile(85));
+        assert_eq!(stream.next().await, None);
+        assert_eq!(stream.finished().await, Err(GoError::Draining));
+        let mut timed = go_stream_timed(produce, Context::profile(85));
+        assert_eq!(timed.next().await, None);
+        assert_eq!(timed.finished().await, Err(GoError::Draining));
+        let (tx, _rx) = channel::<i32>();
+        let sent = go!(
+            tx,
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(1);
+            },
+            85
+        );
+        assert_eq!(sent.err(), Some(GoError::Draining));
+        assert_eq!(run_blocking(85, || 1).await, Err(GoError::Draining));
+        assert_eq!(in_flight(85), 0);
+        set_draining(85, false);
+        let items: Vec<_> = go_stream(produce, Context::profile(85)).collect().await;
+        assert_eq!(items, vec![1]);
+    }
+}
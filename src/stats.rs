@@ -0,0 +1,569 @@
+use crate::prelude::{runtimes_read, GoError};
+use crate::shutdown::is_draining;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::runtime::RuntimeMetrics;
+
+/// per profile counters maintained by the crate for every task it spawns
+pub(crate) struct ProfileCounters {
+    spawned: AtomicU64,
+    in_flight: AtomicU64,
+    completed: AtomicU64,
+    timed_out: AtomicU64,
+    errored: AtomicU64,
+    outstanding: AtomicU64,
+    max_outstanding: AtomicU64,
+    /// moving average of the schedule latency in nanoseconds, 0 before the first sample
+    schedule_latency_ns: AtomicU64,
+}
+
+impl ProfileCounters {
+    const fn new() -> Self {
+        ProfileCounters {
+            spawned: AtomicU64::new(0),
+            in_flight: AtomicU64::new(0),
+            completed: AtomicU64::new(0),
+            timed_out: AtomicU64::new(0),
+            errored: AtomicU64::new(0),
+            outstanding: AtomicU64::new(0),
+            max_outstanding: AtomicU64::new(0),
+            schedule_latency_ns: AtomicU64::new(0),
+        }
+    }
+}
+
+static COUNTERS: [ProfileCounters; 256] = [const { ProfileCounters::new() }; 256];
+
+pub(crate) fn counters(profile: u8) -> &'static ProfileCounters {
+    &COUNTERS[profile as usize]
+}
+
+static LAST_ERRORS: [Mutex<Option<GoError>>; 256] = [const { Mutex::new(None) }; 256];
+
+/// count how a waited for task on `profile` ended, remembering the error if it failed
+pub(crate) fn record_outcome<T>(profile: u8, r: &Result<T, GoError>) {
+    let c = counters(profile);
+    match r {
+        Ok(_) => {
+            c.completed.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        Err(GoError::Timeout { .. }) => c.timed_out.fetch_add(1, Ordering::Relaxed),
+        Err(_) => c.errored.fetch_add(1, Ordering::Relaxed),
+    };
+    if let Err(e) = r {
+        *LAST_ERRORS[profile as usize]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner) = Some(e.clone());
+    }
+}
+
+/// the most recent error a dispatched task on `profile` ended with, if any
+pub fn last_error(profile: u8) -> Option<GoError> {
+    LAST_ERRORS[profile as usize]
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .clone()
+}
+
+/// a task in the in-flight registry read by `dump_inflight`
+struct InflightEntry {
+    profile: u8,
+    spawned_at: Instant,
+    label: Option<String>,
+}
+
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(1);
+
+/// shards of the in-flight registry
+const INFLIGHT_SHARDS: usize = 64;
+
+/// tasks in flight keyed by task id, spread over shards by id. consecutive spawns land on
+/// different shards, so tasks spawned together (on any profile) rarely contend for a lock
+/// and a dump only holds each one for a copy.
+static INFLIGHT: [Mutex<BTreeMap<u64, InflightEntry>>; INFLIGHT_SHARDS] =
+    [const { Mutex::new(BTreeMap::new()) }; INFLIGHT_SHARDS];
+
+fn inflight_shard(id: u64) -> MutexGuard<'static, BTreeMap<u64, InflightEntry>> {
+    INFLIGHT[id as usize % INFLIGHT_SHARDS]
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+}
+
+/// decrements the in-flight gauge and leaves the registry when the tracked future finishes
+/// or gets dropped
+struct InflightGuard {
+    profile: u8,
+    id: u64,
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        inflight_shard(self.id).remove(&self.id);
+        counters(self.profile)
+            .in_flight
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// count `fut` as spawned and in flight on `profile` for as long as it is alive, and sample
+/// how long it waits between being spawned and first polled
+#[doc(hidden)]
+pub fn track<F: Future>(profile: u8, fut: F) -> impl Future<Output = F::Output> {
+    track_labeled(profile, None, fut)
+}
+
+/// `track`, listing the task under `label` in `dump_inflight`
+pub(crate) fn track_labeled<F: Future>(
+    profile: u8,
+    label: Option<String>,
+    fut: F,
+) -> impl Future<Output = F::Output> {
+    let (guard, spawned_at) = start_tracking(profile, label);
+    async move {
+        let _guard = guard;
+        record_schedule_latency(profile, spawned_at.elapsed());
+        fut.await
+    }
+}
+
+/// `track` for a closure run on the blocking pool
+pub(crate) fn track_blocking<R>(
+    profile: u8,
+    label: Option<String>,
+    f: impl FnOnce() -> R,
+) -> impl FnOnce() -> R {
+    let (guard, spawned_at) = start_tracking(profile, label);
+    move || {
+        let _guard = guard;
+        record_schedule_latency(profile, spawned_at.elapsed());
+        f()
+    }
+}
+
+fn start_tracking(profile: u8, label: Option<String>) -> (InflightGuard, Instant) {
+    let c = counters(profile);
+    c.spawned.fetch_add(1, Ordering::Relaxed);
+    c.in_flight.fetch_add(1, Ordering::Relaxed);
+    let spawned_at = Instant::now();
+    let id = NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed);
+    let entry = InflightEntry {
+        profile,
+        spawned_at,
+        label,
+    };
+    inflight_shard(id).insert(id, entry);
+    (InflightGuard { profile, id }, spawned_at)
+}
+
+/// one task in flight, see `dump_inflight`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InflightInfo {
+    /// unique within the process, in spawn order
+    pub task_id: u64,
+    pub profile: u8,
+    /// time since the task was spawned
+    pub age: Duration,
+    /// `Context.label` of the call that spawned it
+    pub label: Option<String>,
+}
+
+/// every task spawned through this crate that hasn't finished yet, oldest first per
+/// profile. handy to find what is stuck when something hangs.
+pub fn dump_inflight() -> Vec<InflightInfo> {
+    let now = Instant::now();
+    let mut tasks: Vec<_> = INFLIGHT
+        .iter()
+        .flat_map(|shard| {
+            shard
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .iter()
+                .map(|(id, entry)| InflightInfo {
+                    task_id: *id,
+                    profile: entry.profile,
+                    age: now - entry.spawned_at,
+                    label: entry.label.clone(),
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    tasks.sort_by_key(|task| (task.profile, task.task_id));
+    tasks
+}
+
+/// weight of the newest sample in the schedule latency average, as 1 / n
+const LATENCY_SMOOTHING: u64 = 8;
+
+fn record_schedule_latency(profile: u8, latency: Duration) {
+    let sample = u64::try_from(latency.as_nanos()).unwrap_or(u64::MAX).max(1);
+    let _ = counters(profile).schedule_latency_ns.fetch_update(
+        Ordering::Relaxed,
+        Ordering::Relaxed,
+        |avg| {
+            Some(match avg {
+                0 => sample,
+                avg => avg - avg / LATENCY_SMOOTHING + sample / LATENCY_SMOOTHING,
+            })
+        },
+    );
+}
+
+/// moving average of how long tasks spawned on `profile` wait for a worker to first poll
+/// them, weighting recent tasks most. it grows as the runtime saturates; zero until a
+/// task has run.
+pub fn avg_schedule_latency(profile: u8) -> Duration {
+    Duration::from_nanos(
+        counters(profile)
+            .schedule_latency_ns
+            .load(Ordering::Relaxed),
+    )
+}
+
+/// releases a result channel slot taken with `acquire_result_slot`
+#[derive(Debug)]
+pub(crate) struct OutstandingGuard(u8);
+
+impl Drop for OutstandingGuard {
+    fn drop(&mut self) {
+        counters(self.0).outstanding.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// count one more result channel on `profile` for as long as the guard lives,
+/// or fail with `GoError::Overloaded` if that would exceed `set_max_outstanding`
+/// and with `GoError::Draining` while the profile is draining
+pub(crate) fn acquire_result_slot(profile: u8) -> Result<OutstandingGuard, GoError> {
+    if is_draining(profile) {
+        return Err(GoError::Draining);
+    }
+    let c = counters(profile);
+    let max = c.max_outstanding.load(Ordering::Relaxed);
+    let before = c.outstanding.fetch_add(1, Ordering::Relaxed);
+    let guard = OutstandingGuard(profile);
+    if max > 0 && before >= max {
+        return Err(GoError::Overloaded);
+    }
+    Ok(guard)
+}
+
+/// cap the result channels (calls whose result hasn't been received or dropped yet) on
+/// `profile`; calls beyond the cap fail with `GoError::Overloaded` without spawning.
+/// this guards against piling up `Pending` handles that are never awaited. 0 removes the cap.
+pub fn set_max_outstanding(profile: u8, max: u64) {
+    counters(profile)
+        .max_outstanding
+        .store(max, Ordering::Relaxed);
+}
+
+/// result channels currently held on `profile`
+pub fn outstanding(profile: u8) -> u64 {
+    counters(profile).outstanding.load(Ordering::Relaxed)
+}
+
+/// tasks spawned through this crate on `profile` that haven't finished yet
+pub fn in_flight(profile: u8) -> u64 {
+    counters(profile).in_flight.load(Ordering::Relaxed)
+}
+
+/// the profile among `profiles` with the fewest tasks in flight, the first one on a tie.
+/// only reads atomics, so it is cheap enough to call for every spawn.
+/// panics if `profiles` is empty.
+pub fn least_loaded(profiles: &[u8]) -> u8 {
+    *profiles
+        .iter()
+        .min_by_key(|p| in_flight(**p))
+        .expect("least_loaded needs at least one profile")
+}
+
+/// a point in time view of one profile
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ProfileDiagnostics {
+    pub profile: u8,
+    pub initialized: bool,
+    /// number of worker threads, `None` when the runtime is not initialized
+    pub workers: Option<usize>,
+    /// tasks alive on the runtime (including ones not spawned by this crate)
+    pub active_tasks: Option<usize>,
+    pub spawned: u64,
+    pub in_flight: u64,
+}
+
+/// diagnostics for every profile that is initialized or has ever spawned a task
+pub fn diagnostics() -> Vec<ProfileDiagnostics> {
+    let rts = runtimes_read();
+    (0..=u8::MAX)
+        .filter_map(|profile| {
+            let c = counters(profile);
+            let spawned = c.spawned.load(Ordering::Relaxed);
+            let metrics = rts[profile as usize].as_ref().map(|r| r.metrics());
+            if metrics.is_none() && spawned == 0 {
+                return None;
+            }
+            Some(ProfileDiagnostics {
+                profile,
+                initialized: metrics.is_some(),
+                workers: metrics.as_ref().map(|m| m.num_workers()),
+                active_tasks: metrics.as_ref().map(|m| m.num_alive_tasks()),
+                spawned,
+                in_flight: c.in_flight.load(Ordering::Relaxed),
+            })
+        })
+        .collect()
+}
+
+/// counters of one profile, see `metrics`
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct ProfileMetrics {
+    pub profile: u8,
+    /// tasks spawned through this crate, by `go!` or any other entry point
+    pub spawned: u64,
+    pub in_flight: u64,
+    /// waited for calls that returned a value
+    pub completed: u64,
+    /// waited for calls that ended with `GoError::Timeout`, counting each attempt
+    pub timed_out: u64,
+    /// waited for calls that failed any other way, e.g. a panic or a dropped sender
+    pub errored: u64,
+    /// from tokio's `RuntimeMetrics`, `None` when the runtime is not initialized
+    pub workers: Option<usize>,
+    /// tasks alive on the runtime (including ones not spawned by this crate)
+    pub alive_tasks: Option<usize>,
+}
+
+/// the counters the crate keeps for `profile`, along with its runtime's metrics.
+/// calls rejected before spawning (draining, overloaded, rate limited) aren't counted.
+pub fn metrics(profile: u8) -> ProfileMetrics {
+    let runtime_metrics = runtimes_read()[profile as usize]
+        .as_ref()
+        .map(|r| r.metrics());
+    profile_metrics(profile, runtime_metrics)
+}
+
+/// `metrics` of every initialized profile
+pub fn metrics_all() -> Vec<ProfileMetrics> {
+    let rts = runtimes_read();
+    (0..=u8::MAX)
+        .filter_map(|profile| {
+            let runtime_metrics = rts[profile as usize].as_ref()?.metrics();
+            Some(profile_metrics(profile, Some(runtime_metrics)))
+        })
+        .collect()
+}
+
+fn profile_metrics(profile: u8, runtime_metrics: Option<RuntimeMetrics>) -> ProfileMetrics {
+    let c = counters(profile);
+    ProfileMetrics {
+        profile,
+        spawned: c.spawned.load(Ordering::Relaxed),
+        in_flight: c.in_flight.load(Ordering::Relaxed),
+        completed: c.completed.load(Ordering::Relaxed),
+        timed_out: c.timed_out.load(Ordering::Relaxed),
+        errored: c.errored.load(Ordering::Relaxed),
+        workers: runtime_metrics.as_ref().map(|m| m.num_workers()),
+        alive_tasks: runtime_metrics.as_ref().map(|m| m.num_alive_tasks()),
+    }
+}
+
+/// dump `diagnostics()` as a JSON array, handy for dump-on-signal debugging
+#[cfg(feature = "serde")]
+pub fn diagnostics_json() -> String {
+    serde_json::to_string(&diagnostics()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::go;
+    use crate::prelude::*;
+
+    #[tokio::test]
+    async fn diagnostics_lists_initialized_profiles() {
+        let r = go!(
+            |tx: Sender<i32>| async move {
+                let _ = tx.send(1);
+            },
+            Context {
+                profile: 11,
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r.unwrap(), 1);
+        let d = diagnostics().into_iter().find(|d| d.profile == 11).unwrap();
+        assert!(d.initialized);
+        assert!(d.workers.unwrap() > 0);
+        assert_eq!(d.spawned, 1);
+    }
+
+    #[tokio::test]
+    async fn metrics_count_outcomes() {
+        let ctx = Context::profile(4).timeout(Duration::from_millis(50));
+        for i in 0..10 {
+            let r = go!(
+                |tx: Sender<i32>| async move {
+                    let _ = tx.send(i);
+                },
+                ctx.clone()
+            )
+            .await;
+            assert_eq!(r, Ok(i));
+        }
+        for _ in 0..2 {
+            let r = go_with(
+                |tx: Sender<i32>| async move {
+                    sleep(Duration::from_secs(5)).await;
+                    let _ = tx.send(0);
+                },
+                ctx.clone(),
+            )
+            .await;
+            assert!(r.is_err());
+        }
+        sleep(Duration::from_millis(20)).await;
+        let m = metrics(4);
+        assert_eq!(
+            (m.spawned, m.in_flight, m.completed, m.timed_out, m.errored),
+            (12, 0, 10, 2, 0)
+        );
+        assert!(m.workers.unwrap() > 0);
+        assert!(metrics_all().contains(&metrics(4)));
+        assert!(metrics_all().iter().all(|m| m.workers.is_some()));
+        assert_eq!(metrics(62).workers, None);
+    }
+
+    #[tokio::test]
+    async fn schedule_latency_grows_under_load() {
+        configure_from(GoConfig {
+            profiles: vec![ProfileConfig {
+                profile: 47,
+                worker_threads: Some(1),
+                ..Default::default()
+            }],
+        });
+        let ctx = Context {
+            profile: 47,
+            ..Default::default()
+        };
+        assert_eq!(avg_schedule_latency(ctx.profile), Duration::ZERO);
+        let busy = go!(
+            |tx: Sender<()>| async move {
+                std::thread::sleep(Duration::from_millis(300));
+                let _ = tx.send(());
+            },
+            ctx.clone()
+        );
+        let queued = go!(
+            |tx: Sender<()>| async move {
+                let _ = tx.send(());
+            },
+            ctx.clone()
+        );
+        let (a, b) = tokio::join!(busy, queued);
+        assert_eq!((a, b), (Ok(()), Ok(())));
+        assert!(avg_schedule_latency(ctx.profile) >= Duration::from_millis(10));
+    }
+
+    #[tokio::test]
+    async fn dump_lists_running_tasks_by_age() {
+        let mut calls = Vec::new();
+        for i in 0..3 {
+            calls.push(tokio::spawn(go!(
+                |tx: Sender<()>| async move {
+                    sleep(Duration::from_secs(5)).await;
+                    let _ = tx.send(());
+                },
+                Context {
+                    profile: 56,
+                    label: Some(format!("job-{}", i)),
+                    ..Default::default()
+                }
+            )));
+            sleep(Duration::from_millis(50)).await;
+        }
+        let dump: Vec<_> = dump_inflight()
+            .into_iter()
+            .filter(|t| t.profile == 56)
+            .collect();
+        let labels: Vec<_> = dump.iter().map(|t| t.label.as_deref().unwrap()).collect();
+        assert_eq!(labels, vec!["job-0", "job-1", "job-2"]);
+        assert!(dump[0].age > dump[1].age && dump[1].age > dump[2].age);
+        assert!(dump[2].age >= Duration::from_millis(40));
+        for call in calls {
+            call.abort();
+        }
+    }
+
+    #[tokio::test]
+    async fn dump_gathers_tasks_from_every_shard() {
+        let calls: Vec<_> = (0..200)
+            .map(|_| {
+                tokio::spawn(go!(
+                    |tx: Sender<()>| async move {
+                        sleep(Duration::from_millis(200)).await;
+                        let _ = tx.send(());
+                    },
+                    80
+                ))
+            })
+            .collect();
+        while in_flight(80) < 200 {
+            sleep(Duration::from_millis(5)).await;
+        }
+        let ids: Vec<_> = dump_inflight()
+            .into_iter()
+            .filter(|t| t.profile == 80)
+            .map(|t| t.task_id)
+            .collect();
+        assert_eq!(ids.len(), 200);
+        assert!(ids.windows(2).all(|w| w[0] < w[1]));
+        for call in calls {
+            assert_eq!(call.await.unwrap(), Ok(()));
+        }
+        // a task leaves the registry as it ends, just after sending its result
+        while in_flight(80) > 0 {
+            sleep(Duration::from_millis(5)).await;
+        }
+        assert!(dump_inflight().iter().all(|t| t.profile != 80));
+    }
+
+    #[tokio::test]
+    async fn last_error_records_timeout() {
+        assert_eq!(last_error(29), None);
+        let r = go!(
+            |tx: Sender<()>| async move {
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(());
+            },
+            Context {
+                profile: 29,
+                timeout: Duration::from_millis(20),
+                ..Default::default()
+            }
+        )
+        .await;
+        assert_eq!(r, Err(GoError::Timeout { attempts: 1 }));
+        assert_eq!(last_error(29), Some(GoError::Timeout { attempts: 1 }));
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn diagnostics_json_has_fields() {
+        init_runtime(12);
+        let v: serde_json::Value = serde_json::from_str(&diagnostics_json()).unwrap();
+        let p = v
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|p| p["profile"] == 12)
+            .unwrap();
+        assert_eq!(p["initialized"], true);
+        for field in ["workers", "active_tasks", "spawned", "in_flight"] {
+            assert!(p[field].is_number(), "missing {}", field);
+        }
+    }
+}
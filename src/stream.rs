@@ -0,0 +1,407 @@
+use crate::cancel::cancel_scope;
+use crate::dispatch::{admit_now, task_failure, timer_limit};
+use crate::prelude::*;
+use crate::stats::OutstandingGuard;
+use crate::trace::TaskTrace;
+use futures::Stream;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context as TaskContext, Poll};
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, Sleep};
+
+/// channel capacity used by `go_stream!` when `Context.stream_capacity` is 0
+pub const DEFAULT_STREAM_CAPACITY: usize = 32;
+
+/// the items sent by a `go_stream!` task. ends once the producer is done and everything
+/// buffered has been read, or when the item timeout passes without a new item. a stream
+/// the profile refused is empty, its producer never spawned.
+pub struct GoStream<T> {
+    receiver: mpsc::Receiver<T>,
+    /// with the runtime it runs on, `Err` with why it wasn't spawned
+    producer: Result<(Handle, JoinHandle<()>), GoError>,
+    /// the stream counts as an outstanding result on its profile until dropped
+    _slot: Option<OutstandingGuard>,
+    profile: u8,
+    item_timeout: Option<Duration>,
+    deadline: Option<Pin<Box<Sleep>>>,
+    timed_out: bool,
+    trace: TaskTrace,
+}
+
+impl<T> GoStream<T> {
+    /// whether the stream ended because the producer stalled for longer than
+    /// `Context.item_timeout`
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+
+    /// abort the producer task; items already buffered can still be read
+    pub fn abort(&self) {
+        if let Ok((_, producer)) = &self.producer {
+            producer.abort();
+        }
+    }
+
+    /// wait for the producer task to end and report how it ended, e.g. to notice a panic
+    /// after the last item, or why the profile refused it. drain the stream first
+    /// (`by_ref()`), unread items are dropped.
+    pub async fn finished(self) -> Result<(), GoError> {
+        drop(self.receiver);
+        if self.timed_out {
+            return Err(GoError::Timeout { attempts: 1 });
+        }
+        let (handle, producer) = self.producer?;
+        match producer.await {
+            Ok(()) => Ok(()),
+            Err(e) => Err(task_failure(self.profile, &handle, e)),
+        }
+    }
+}
+
+impl<T> Stream for GoStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<T>> {
+        if self.timed_out {
+            return Poll::Ready(None);
+        }
+        match self.receiver.poll_recv(cx) {
+            Poll::Ready(item) => {
+                if let (Some(d), Some(deadline)) = (self.item_timeout, &mut self.deadline) {
+                    deadline.as_mut().reset(Instant::now() + d);
+                }
+                Poll::Ready(item)
+            }
+            Poll::Pending => {
+                let stalled = match &mut self.deadline {
+                    Some(deadline) => deadline.as_mut().poll(cx).is_ready(),
+                    None => false,
+                };
+                if !stalled {
+                    return Poll::Pending;
+                }
+                self.timed_out = true;
+                self.trace
+                    .finished(&Err::<(), _>(GoError::Timeout { attempts: 1 }));
+                self.abort();
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+/// spawn `f` on `ctx.profile` right away and stream whatever it sends.
+///
+/// `ctx.stream_capacity` bounds how many items may be buffered before the producer's
+/// `send().await` has to wait for the consumer: a small buffer keeps the producer in
+/// lockstep with the consumer (tight backpressure), a large one lets it run ahead at the
+/// cost of memory.
+///
+/// with `ctx.item_timeout`, or else a non-zero `ctx.timeout`, the stream ends early when the
+/// producer goes that long without sending (counting from the start for the first item);
+/// the producer is aborted and `timed_out()` tells this apart from a regular end. it always
+/// runs on tokio's timer.
+/// # Example:
+/// ```
+/// use tokio_go::prelude::*;
+/// use futures::StreamExt;
+/// async fn count(tx: mpsc::Sender<i32>) {
+///     for i in 0..3 {
+///         let _ = tx.send(i).await;
+///     }
+/// }
+/// #[tokio::main]
+/// async fn main(){
+/// let items: Vec<i32> = go_stream(count, Context::default()).collect().await;
+/// assert_eq!(items, vec![0, 1, 2]);
+/// }
+/// ```
+pub fn go_stream<T, F, Fut>(f: F, ctx: Context) -> GoStream<T>
+where
+    F: FnOnce(mpsc::Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, receiver) = mpsc::channel(stream_capacity(&ctx));
+    let trace = TaskTrace::new(ctx.profile, &ctx);
+    let producer = admit_now(&ctx, cancel_scope(f(sender))).map(|(slot, producer)| {
+        let handle = runtime_handle(ctx.profile);
+        let producer = handle.spawn(track(ctx.profile, trace.watch(producer)));
+        ((handle, producer), slot)
+    });
+    with_producer(receiver, producer, trace, &ctx)
+}
+
+/// `go_stream`, but every item comes with the time since the previous one (for the first,
+/// since the producer was spawned), taken when the producer sends it. meant for profiling
+/// a producer's throughput without instrumenting it.
+#[doc(hidden)]
+pub fn go_stream_timed<T, F, Fut>(f: F, ctx: Context) -> GoStream<(T, Duration)>
+where
+    F: FnOnce(mpsc::Sender<T>) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+    T: Send + 'static,
+{
+    let (timed, receiver) = mpsc::channel(stream_capacity(&ctx));
+    let trace = TaskTrace::new(ctx.profile, &ctx);
+    // a 1 item hop stamps items as they are sent while keeping the producer's backpressure
+    let (sender, mut sent) = mpsc::channel::<T>(1);
+    let producer = admit_now(&ctx, cancel_scope(f(sender))).map(|(slot, producer)| {
+        let handle = runtime_handle(ctx.profile);
+        let producer = handle.spawn(track(ctx.profile, trace.watch(producer)));
+        // not tracked: it is part of the producer, which already counts as the stream's task
+        handle.spawn(async move {
+            let mut last = Instant::now();
+            while let Some(item) = sent.recv().await {
+                let now = Instant::now();
+                if timed.send((item, now - last)).await.is_err() {
+                    return;
+                }
+                last = now;
+            }
+        });
+        ((handle, producer), slot)
+    });
+    with_producer(receiver, producer, trace, &ctx)
+}
+
+fn stream_capacity(ctx: &Context) -> usize {
+    match ctx.stream_capacity {
+        0 => DEFAULT_STREAM_CAPACITY,
+        n => n,
+    }
+}
+
+fn with_producer<T>(
+    receiver: mpsc::Receiver<T>,
+    producer: Result<((Handle, JoinHandle<()>), OutstandingGuard), GoError>,
+    trace: TaskTrace,
+    ctx: &Context,
+) -> GoStream<T> {
+    let (producer, slot) = match producer {
+        Ok((producer, slot)) => (Ok(producer), Some(slot)),
+        Err(e) => (Err(e), None),
+    };
+    let item_timeout = match ctx.item_timeout {
+        Some(d) => Some(d),
+        None if ctx.timeout.is_zero() => None,
+        None => Some(ctx.timeout),
+    }
+    .and_then(timer_limit);
+    GoStream {
+        receiver,
+        producer,
+        _slot: slot,
+        profile: ctx.profile,
+        item_timeout,
+        deadline: item_timeout.map(|d| Box::pin(tokio::time::sleep(d))),
+        timed_out: false,
+        trace,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::prelude::*;
+    use crate::{go_stream, go_stream_timed};
+    use futures::StreamExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn stream_collects_all_items() {
+        let items: Vec<i32> = go_stream!(|tx: mpsc::Sender<i32>| async move {
+            for i in 0..5 {
+                let _ = tx.send(i).await;
+            }
+        })
+        .collect()
+        .await;
+        assert_eq!(items, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn capacity_one_blocks_producer() {
+        let sent = Arc::new(AtomicUsize::new(0));
+        let counter = sent.clone();
+        let mut stream = go_stream!(
+            |tx: mpsc::Sender<i32>| async move {
+                for i in 0..3 {
+                    if tx.send(i).await.is_err() {
+                        return;
+                    }
+                    counter.fetch_add(1, Ordering::SeqCst);
+                }
+            },
+            Context {
+                profile: 16,
+                stream_capacity: 1,
+                ..Default::default()
+            }
+        );
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        assert_eq!(stream.next().await, Some(0));
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(sent.load(Ordering::SeqCst), 2);
+        assert_eq!(stream.next().await, Some(1));
+        assert_eq!(stream.next().await, Some(2));
+        assert_eq!(stream.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn producer_panic_observable_after_drain() {
+        let mut stream = go_stream!(
+            |tx: mpsc::Sender<i32>| async move {
+                for i in 0..3 {
+                    let _ = tx.send(i).await;
+                }
+                drop(tx);
+                panic!("after items");
+            },
+            Context {
+                profile: 16,
+                ..Default::default()
+            }
+        );
+        let items: Vec<i32> = stream.by_ref().collect().await;
+        assert_eq!(items, vec![0, 1, 2]);
+        assert_eq!(
+            stream.finished().await,
+            Err(GoError::Panicked("after items".to_string()))
+        );
+
+        let stream = go_stream!(|tx: mpsc::Sender<i32>| async move {
+            let _ = tx.send(1).await;
+        });
+        assert_eq!(stream.finished().await, Ok(()));
+    }
+
+    #[tokio::test]
+    async fn timed_stream_reports_gaps() {
+        let items: Vec<(i32, Duration)> = go_stream_timed!(|tx: mpsc::Sender<i32>| async move {
+            for i in 0..3 {
+                sleep(Duration::from_millis(100)).await;
+                let _ = tx.send(i).await;
+            }
+        })
+        .collect()
+        .await;
+        assert_eq!(
+            items.iter().map(|(i, _)| *i).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        for (_, gap) in items {
+            assert!(gap >= Duration::from_millis(80), "gap {:?}", gap);
+            assert!(gap < Duration::from_millis(500), "gap {:?}", gap);
+        }
+    }
+
+    #[tokio::test]
+    async fn timed_stream_counts_as_one_task() {
+        let mut stream = go_stream_timed!(
+            |tx: mpsc::Sender<i32>| async move {
+                let _ = tx.send(1).await;
+                sleep(Duration::from_millis(50)).await;
+            },
+            Context::profile(76)
+        );
+        assert_eq!(stream.next().await.map(|(i, _)| i), Some(1));
+        assert_eq!(metrics(76).in_flight, 1);
+        assert_eq!(stream.by_ref().count().await, 0);
+        assert_eq!(stream.finished().await, Ok(()));
+        assert_eq!(metrics(76).spawned, 1);
+    }
+
+    #[tokio::test]
+    async fn paced_items_arrive_in_order() {
+        let items: Vec<i32> = go_stream!(
+            |tx: mpsc::Sender<i32>| async move {
+                for i in 1..=5 {
+                    sleep(Duration::from_millis(10)).await;
+                    let _ = tx.send(i).await;
+                }
+            },
+            Context {
+                profile: 1,
+                timeout: Duration::from_secs(1),
+                ..Default::default()
+            }
+        )
+        .collect()
+        .await;
+        assert_eq!(items, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn timeout_applies_per_item() {
+        let mut stream = go_stream(
+            |tx: mpsc::Sender<i32>| async move {
+                for i in 0..3 {
+                    sleep(Duration::from_millis(60)).await;
+                    let _ = tx.send(i).await;
+                }
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(3).await;
+            },
+            Context {
+                profile: 16,
+                timeout: Duration::from_millis(150),
+                ..Default::default()
+            },
+        );
+        let items: Vec<i32> = stream.by_ref().collect().await;
+        assert_eq!(items, vec![0, 1, 2]);
+        assert!(stream.timed_out());
+    }
+
+    #[tokio::test]
+    async fn stalled_producer_hits_item_timeout() {
+        let mut stream = go_stream!(
+            |tx: mpsc::Sender<i32>| async move {
+                for i in 0..2 {
+                    sleep(Duration::from_millis(20)).await;
+                    let _ = tx.send(i).await;
+                }
+                sleep(Duration::from_secs(5)).await;
+                let _ = tx.send(2).await;
+            },
+            Context {
+                profile: 16,
+                item_timeout: Some(Duration::from_millis(150)),
+                ..Default::default()
+            }
+        );
+        let items: Vec<i32> = stream.by_ref().collect().await;
+        assert_eq!(items, vec![0, 1]);
+        assert!(stream.timed_out());
+        assert_eq!(
+            stream.finished().await,
+            Err(GoError::Timeout { attempts: 1 })
+        );
+    }
+
+    #[tokio::test]
+    async fn producer_failures_are_classified_like_go() {
+        let mut stream = go_stream!(
+            |tx: mpsc::Sender<i32>| async move {
+                let _ = tx.send(0).await;
+                sleep(Duration::from_secs(5)).await;
+            },
+            88
+        );
+        assert_eq!(stream.next().await, Some(0));
+        shutdown_runtime(88, Duration::ZERO);
+        assert_eq!(stream.next().await, None);
+        assert_eq!(stream.finished().await, Err(GoError::RuntimeShutdown));
+
+        let stream = go_stream!(
+            |_tx: mpsc::Sender<i32>| async move {
+                sleep(Duration::from_secs(5)).await;
+            },
+            88
+        );
+        stream.abort();
+        assert_eq!(stream.finished().await, Err(GoError::Cancelled));
+    }
+}
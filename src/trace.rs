@@ -0,0 +1,137 @@
+use crate::prelude::*;
+use std::future::Future;
+
+/// the `go_task` span a task runs in and the event recorded once it completes, with the
+/// `tracing` feature. without it this is empty and every method passes its input through.
+#[derive(Clone, Debug)]
+pub(crate) struct TaskTrace {
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    #[cfg(feature = "tracing")]
+    spawned_at: std::time::Instant,
+    /// set by the first `finished`, a task is reported once whoever notices its end first
+    #[cfg(feature = "tracing")]
+    reported: Arc<std::sync::atomic::AtomicBool>,
+}
+
+#[cfg(feature = "tracing")]
+impl TaskTrace {
+    /// a `go_task{profile, timeout_ms}` span for a task on `profile` spawned for `ctx`, a
+    /// child of the caller's current span
+    pub(crate) fn new(profile: u8, ctx: &Context) -> Self {
+        TaskTrace {
+            span: tracing::info_span!(
+                "go_task",
+                profile,
+                timeout_ms = ctx.timeout.as_millis() as u64
+            ),
+            spawned_at: std::time::Instant::now(),
+            reported: Default::default(),
+        }
+    }
+
+    /// `fut` running in the span, with its events going to the caller's subscriber
+    pub(crate) fn instrument<F: Future>(&self, fut: F) -> impl Future<Output = F::Output> {
+        use tracing::instrument::{Instrument, WithSubscriber};
+        fut.instrument(self.span.clone()).with_current_subscriber()
+    }
+
+    /// `instrument`, also recording the completion event from within the task when it ends
+    /// (`ok`), panics (`panic`) or is aborted (`cancelled`), for tasks no call waits on
+    pub(crate) fn watch<F: Future<Output = ()>>(&self, fut: F) -> impl Future<Output = ()> {
+        let end = Ended {
+            trace: self.clone(),
+            done: false,
+        };
+        self.instrument(async move {
+            let mut end = end;
+            fut.await;
+            end.done = true;
+        })
+    }
+
+    /// a blocking closure running in the span, with the caller's subscriber
+    pub(crate) fn blocking<R>(&self, f: impl FnOnce() -> R) -> impl FnOnce() -> R {
+        let span = self.span.clone();
+        let dispatch = tracing::dispatcher::get_default(|d| d.clone());
+        move || tracing::dispatcher::with_default(&dispatch, || span.in_scope(f))
+    }
+
+    /// record that the task's call ended with `r`, unless that was recorded already: a
+    /// `warn` event for a timeout, `debug` otherwise, with the time since the spawn
+    pub(crate) fn finished<T>(&self, r: &Result<T, GoError>) {
+        use std::sync::atomic::Ordering;
+        if self.reported.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        let span = &self.span;
+        let elapsed_ms = self.spawned_at.elapsed().as_millis() as u64;
+        let outcome = outcome_name(r);
+        if let Err(GoError::Timeout { .. }) = r {
+            tracing::warn!(parent: span, elapsed_ms, outcome, "go task timed out");
+        } else {
+            tracing::debug!(parent: span, elapsed_ms, outcome, "go task finished");
+        }
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl TaskTrace {
+    pub(crate) fn new(_profile: u8, _ctx: &Context) -> Self {
+        TaskTrace {}
+    }
+
+    pub(crate) fn instrument<F: Future>(&self, fut: F) -> F {
+        fut
+    }
+
+    pub(crate) fn watch<F: Future<Output = ()>>(&self, fut: F) -> F {
+        fut
+    }
+
+    pub(crate) fn blocking<F>(&self, f: F) -> F {
+        f
+    }
+
+    pub(crate) fn finished<T>(&self, _r: &Result<T, GoError>) {}
+}
+
+/// reports a watched task when it is dropped: finished if `done`, else by a panic or abort
+#[cfg(feature = "tracing")]
+struct Ended {
+    trace: TaskTrace,
+    done: bool,
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for Ended {
+    fn drop(&mut self) {
+        let r = if self.done {
+            Ok(())
+        } else if std::thread::panicking() {
+            Err(GoError::Panicked(String::new()))
+        } else {
+            Err(GoError::Cancelled)
+        };
+        self.trace.finished(&r);
+    }
+}
+
+/// the `outcome` field of a task's completion event
+#[cfg(feature = "tracing")]
+fn outcome_name<T>(r: &Result<T, GoError>) -> &'static str {
+    match r {
+        Ok(_) => "ok",
+        Err(GoError::Timeout { .. }) => "timeout",
+        Err(GoError::SenderDropped) => "sender_dropped",
+        Err(GoError::Panicked(_)) => "panic",
+        Err(GoError::Cancelled) => "cancelled",
+        Err(GoError::Overloaded) => "overloaded",
+        Err(GoError::Draining) => "draining",
+        Err(GoError::Interrupted) => "interrupted",
+        Err(GoError::RateLimited) => "rate_limited",
+        Err(GoError::RuntimeShutdown) => "runtime_shutdown",
+        Err(GoError::NoRuntime) => "no_runtime",
+        Err(GoError::ReservedProfile) => "reserved_profile",
+    }
+}
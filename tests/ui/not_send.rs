@@ -0,0 +1,11 @@
+use std::rc::Rc;
+use tokio_go::prelude::*;
+
+fn main() {
+    let shared = Rc::new(1);
+    let f = move |tx: Sender<i32>| async move {
+        sleep(Duration::from_millis(1)).await;
+        let _ = tx.send(*shared);
+    };
+    assert_go_compatible(&f);
+}
@@ -0,0 +1,64 @@
+//! `#[derive(Profile)]` for tokio-go, use it through the `derive` feature of `tokio-go`.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields};
+
+/// implement `tokio_go::prelude::Profile` for a `#[repr(u8)]` enum of unit variants,
+/// using each variant's discriminant as the profile index
+#[proc_macro_derive(Profile)]
+pub fn derive_profile(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+    let data = match &input.data {
+        Data::Enum(data) => data,
+        _ => {
+            return Err(Error::new_spanned(
+                name,
+                "Profile can only be derived for enums",
+            ))
+        }
+    };
+    let mut repr_u8 = false;
+    for attr in input.attrs.iter().filter(|a| a.path().is_ident("repr")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("u8") {
+                repr_u8 = true;
+            }
+            Ok(())
+        })?;
+    }
+    if !repr_u8 {
+        return Err(Error::new_spanned(
+            name,
+            "Profile can only be derived for #[repr(u8)] enums",
+        ));
+    }
+    let mut arms = Vec::new();
+    for variant in &data.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(Error::new_spanned(
+                variant,
+                "Profile variants can't have fields",
+            ));
+        }
+        let v = &variant.ident;
+        arms.push(quote! { #name::#v => #name::#v as u8 });
+    }
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::tokio_go::prelude::Profile for #name #ty_generics #where_clause {
+            fn index(&self) -> u8 {
+                match self {
+                    #(#arms,)*
+                }
+            }
+        }
+    })
+}